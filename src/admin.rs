@@ -0,0 +1,144 @@
+//! Local HTTP API for organizers
+//!
+//! Served on `ADMIN_LISTEN_ADDR` when `ADMIN_API_KEY` is set. Every request must
+//! carry the key as a bearer token.
+
+use std::env;
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::json;
+use subtle::ConstantTimeEq;
+use tracing::info;
+
+use crate::events;
+use crate::guest;
+use crate::hardware::door::{OpenRequest, OpenSource, diagnostics, link};
+use crate::http::{self, Request, Response};
+use crate::mode;
+use crate::status;
+
+/// Address the admin API listens on when `ADMIN_LISTEN_ADDR` is unset
+pub const DEFAULT_LISTEN_ADDR: &str = "127.0.0.1:8080";
+
+#[derive(Deserialize)]
+struct OpenHouseRequest {
+    duration_secs: u64,
+    /// Re-actuation interval, or unset to open on every tap instead
+    interval_secs: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct GuestPassRequest {
+    label: String,
+    duration_secs: u64,
+    /// How many times the pass opens the door, once if unset
+    max_uses: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct RedeemRequest {
+    code: String,
+    presence_code: String,
+}
+
+/// Compares in constant time, so the key cannot be guessed a byte at a time
+fn is_authorized(request: &Request, api_key: &str) -> bool {
+    request
+        .headers
+        .get("authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| token.as_bytes().ct_eq(api_key.as_bytes()).into())
+}
+
+fn handle_request(request: &Request, api_key: &str) -> Response {
+    if !is_authorized(request, api_key) {
+        return Response::empty(401);
+    }
+
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/status") => Response::json(200, &status::report()),
+        ("GET", "/door-module") => Response::json(
+            200,
+            &json!({ "link": link::state(), "diagnostics": diagnostics::state() }),
+        ),
+        ("POST", "/open-house") => {
+            let Ok(body) = serde_json::from_slice::<OpenHouseRequest>(&request.body) else {
+                return Response::empty(400);
+            };
+            match mode::start_open_house(
+                Duration::from_secs(body.duration_secs),
+                body.interval_secs.map(Duration::from_secs),
+                "admin_api",
+            ) {
+                Some(open_house) => Response::json(200, &open_house),
+                // Lockdown has to be lifted first
+                None => Response::empty(409),
+            }
+        }
+        ("DELETE", "/open-house") => {
+            mode::stop_open_house("admin_api");
+            Response::empty(204)
+        }
+        ("POST", "/lockdown") => {
+            mode::start_lockdown("admin_api");
+            Response::json(200, &mode::lockdown())
+        }
+        ("DELETE", "/lockdown") => {
+            mode::lift_lockdown("admin_api");
+            Response::empty(204)
+        }
+        ("GET", "/guest-passes") => Response::json(200, &guest::list()),
+        ("POST", "/guest-passes") => {
+            let Ok(body) = serde_json::from_slice::<GuestPassRequest>(&request.body) else {
+                return Response::empty(400);
+            };
+            let pass = guest::create(
+                body.label,
+                Duration::from_secs(body.duration_secs),
+                body.max_uses.unwrap_or(1),
+                "admin_api",
+            );
+            Response::json(200, &pass)
+        }
+        ("POST", "/guest-passes/redeem") => {
+            let Ok(body) = serde_json::from_slice::<RedeemRequest>(&request.body) else {
+                return Response::empty(400);
+            };
+            match guest::redeem(&body.code, &body.presence_code, "admin_api") {
+                Ok(pass) => {
+                    events::request_open(OpenRequest::new(
+                        OpenSource::GuestPass,
+                        Some(pass.label),
+                        "admin_api",
+                    ));
+                    Response::json(200, &json!({ "uses_left": pass.uses_left }))
+                }
+                Err(e) => Response::json(403, &json!({ "error": e.to_string() })),
+            }
+        }
+        ("DELETE", path) if path.starts_with("/guest-passes/") => {
+            let id = &path["/guest-passes/".len()..];
+            if guest::revoke(id, "admin_api") {
+                Response::empty(204)
+            } else {
+                Response::empty(404)
+            }
+        }
+        _ => Response::empty(404),
+    }
+}
+
+/// Serves the admin API in the background, unless `ADMIN_API_KEY` is unset
+pub fn spawn_server() {
+    let Ok(api_key) = env::var("ADMIN_API_KEY") else {
+        info!("ADMIN_API_KEY is unset, admin API disabled");
+        return;
+    };
+    let addr = env::var("ADMIN_LISTEN_ADDR").unwrap_or_else(|_| DEFAULT_LISTEN_ADDR.into());
+
+    http::spawn_server("admin", addr, move |request| {
+        let api_key = api_key.clone();
+        async move { handle_request(&request, &api_key) }
+    });
+}
@@ -0,0 +1,58 @@
+//! Alerts for organizers, forwarded to the server over the websocket
+//!
+//! Alerts raised while the websocket is disconnected are only logged.
+
+use std::sync::LazyLock;
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tracing::warn;
+
+/// Alerts kept for a slow websocket before the oldest are dropped
+const ALERT_BUFFER: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertKind {
+    /// A global or per-passport rate limit started refusing scans
+    RateLimited,
+    /// A tag was locked out after repeated invalid scans
+    Lockout,
+    /// Someone has been at the door for a while without tapping in
+    Waiting,
+    /// More than one BLE device matched as ada-pusher, so none was connected
+    #[cfg(feature = "ada_pusher")]
+    AmbiguousDoorModule,
+    /// ada-pusher cannot confirm presses, but `ADA_PUSHER_REQUIRE_CONFIRM` is set
+    #[cfg(feature = "ada_pusher")]
+    UnconfirmableDoorModule,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Alert {
+    pub kind: AlertKind,
+    pub message: String,
+    /// When the alert was raised, as a Unix timestamp
+    pub timestamp: i64,
+}
+
+static ALERTS: LazyLock<broadcast::Sender<Alert>> =
+    LazyLock::new(|| broadcast::channel(ALERT_BUFFER).0);
+
+/// Logs an alert and forwards it to organizers
+pub fn raise(kind: AlertKind, message: String) {
+    warn!(?kind, message, "alert raised");
+    // Failing to send only means no websocket is connected to forward it
+    let _ = ALERTS.send(Alert {
+        kind,
+        message,
+        timestamp: Utc::now().timestamp(),
+    });
+}
+
+/// Receives alerts raised from now on
+#[must_use]
+pub fn subscribe() -> broadcast::Receiver<Alert> {
+    ALERTS.subscribe()
+}
@@ -0,0 +1,277 @@
+//! Sounds for scan outcomes, so feedback does not rely on seeing the screen
+//!
+//! With the `audio` feature, a distinct sound is played for a tap being read,
+//! the door opening, each reason a tap is refused and the door not being
+//! ready. Without it, or with `AUDIO_BACKEND=null`, nothing is played.
+//!
+//! Audio is configured with:
+//!
+//! - `AUDIO_BACKEND`: `speaker` (the default) or `null`
+//! - `AUDIO_VOLUME`: 0 to 100 (default 70)
+//! - `AUDIO_QUIET_HOURS`: daily windows such as `22:00-08:00` when
+//!   `AUDIO_QUIET_VOLUME` is used instead (default 0, muted)
+//! - `AUDIO_SOUND_DIR`: directory of custom sounds, named after their cue,
+//!   e.g. `valid.wav` or `invalid.ogg`, replacing the built-in tones
+
+#[cfg(feature = "audio")]
+mod speaker;
+
+use std::env;
+#[cfg(feature = "audio")]
+use std::path::PathBuf;
+use std::thread;
+
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{info, warn};
+
+use crate::enums::AuthState;
+use crate::events::{self, Envelope, Event};
+use crate::policy::DailyHours;
+
+/// Volume used when `AUDIO_VOLUME` is unset
+pub const DEFAULT_VOLUME: u8 = 70;
+
+/// Something worth making a sound about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cue {
+    /// A tag was read and is being checked
+    Pending,
+    Valid,
+    Invalid,
+    NetError,
+    NfcError,
+    OutsideHours,
+    Lockdown,
+    RateLimited,
+    DoorNotReady,
+}
+
+impl Cue {
+    /// The cue for an auth state, if it makes a sound
+    #[must_use]
+    pub fn for_state(state: AuthState) -> Option<Self> {
+        Some(match state {
+            AuthState::Idle => return None,
+            AuthState::Pending => Self::Pending,
+            AuthState::Valid => Self::Valid,
+            AuthState::Invalid => Self::Invalid,
+            AuthState::NetError => Self::NetError,
+            AuthState::NFCError => Self::NfcError,
+            AuthState::DoorHWNotReady => Self::DoorNotReady,
+            AuthState::OutsideHours { .. } => Self::OutsideHours,
+            AuthState::Lockdown => Self::Lockdown,
+            AuthState::RateLimited { .. } => Self::RateLimited,
+        })
+    }
+
+    /// Name of the cue, which custom sound files are named after
+    #[cfg(feature = "audio")]
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Valid => "valid",
+            Self::Invalid => "invalid",
+            Self::NetError => "net_error",
+            Self::NfcError => "nfc_error",
+            Self::OutsideHours => "outside_hours",
+            Self::Lockdown => "lockdown",
+            Self::RateLimited => "rate_limited",
+            Self::DoorNotReady => "door_not_ready",
+        }
+    }
+}
+
+/// Where sounds are played
+trait AudioBackend {
+    /// Plays a cue at `volume`, from 0.0 to 1.0, cutting off whatever was playing
+    fn play(&mut self, cue: Cue, volume: f32);
+}
+
+/// Plays nothing, for headless machines and tests
+struct Null;
+
+impl AudioBackend for Null {
+    fn play(&mut self, _cue: Cue, _volume: f32) {}
+}
+
+struct Config {
+    volume: u8,
+    quiet_hours: Option<DailyHours>,
+    quiet_volume: u8,
+    #[cfg(feature = "audio")]
+    sound_dir: Option<PathBuf>,
+}
+
+impl Config {
+    /// Reads the audio configuration, warning about and ignoring invalid values
+    fn from_env() -> Self {
+        let volume = |var: &str, default: u8| {
+            env::var(var)
+                .ok()
+                .and_then(|volume| volume.parse().ok())
+                .filter(|volume| *volume <= 100)
+                .unwrap_or(default)
+        };
+        let quiet_hours = env::var("AUDIO_QUIET_HOURS").ok().and_then(|hours| {
+            let parsed = DailyHours::parse(&hours);
+            if parsed.is_none() {
+                warn!(value = hours, "invalid AUDIO_QUIET_HOURS, ignoring");
+            }
+            parsed
+        });
+
+        Self {
+            volume: volume("AUDIO_VOLUME", DEFAULT_VOLUME),
+            quiet_hours,
+            quiet_volume: volume("AUDIO_QUIET_VOLUME", 0),
+            #[cfg(feature = "audio")]
+            sound_dir: env::var("AUDIO_SOUND_DIR").ok().map(PathBuf::from),
+        }
+    }
+
+    /// Volume to play at right now, from 0.0 to 1.0
+    fn volume_now(&self) -> f32 {
+        let quiet = self
+            .quiet_hours
+            .as_ref()
+            .is_some_and(DailyHours::contains_now);
+        let volume = if quiet {
+            self.quiet_volume
+        } else {
+            self.volume
+        };
+        f32::from(volume) / 100.0
+    }
+}
+
+#[cfg(feature = "audio")]
+fn open_speaker(config: &Config) -> Box<dyn AudioBackend> {
+    match speaker::Speaker::open(config.sound_dir.clone()) {
+        Ok(speaker) => Box::new(speaker),
+        Err(e) => {
+            warn!(error = %e, "failed to open audio output, playing no sounds");
+            Box::new(Null)
+        }
+    }
+}
+
+#[cfg(not(feature = "audio"))]
+fn open_speaker(_config: &Config) -> Box<dyn AudioBackend> {
+    info!("built without the audio feature, playing no sounds");
+    Box::new(Null)
+}
+
+fn open_backend(config: &Config) -> Box<dyn AudioBackend> {
+    match env::var("AUDIO_BACKEND").as_deref() {
+        Err(_) | Ok("speaker") => open_speaker(config),
+        Ok("null") => Box::new(Null),
+        Ok(other) => {
+            warn!(value = other, "unknown AUDIO_BACKEND, playing no sounds");
+            Box::new(Null)
+        }
+    }
+}
+
+/// Plays the cue for an auth state, unless it has none or is muted, returning
+/// the cue played
+fn play_state(backend: &mut dyn AudioBackend, config: &Config, state: AuthState) -> Option<Cue> {
+    let cue = Cue::for_state(state)?;
+    let volume = config.volume_now();
+    if volume > 0.0 {
+        backend.play(cue, volume);
+        Some(cue)
+    } else {
+        None
+    }
+}
+
+/// Starts the audio thread, playing auth states published from now on
+pub fn spawn_player() {
+    let mut events = events::subscribe();
+    // Audio outputs cannot move between threads, so the backend lives on its own
+    thread::spawn(move || {
+        let config = Config::from_env();
+        let mut backend = open_backend(&config);
+        loop {
+            let state = match events.blocking_recv() {
+                Ok(Envelope {
+                    event: Event::Auth(state),
+                    ..
+                }) => state,
+                Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            };
+            play_state(backend.as_mut(), &config, state);
+        }
+        info!("audio player stopped");
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Quiet hours covering the whole day, or none of it
+    fn config(always_quiet: bool) -> Config {
+        let hours = if always_quiet {
+            "00:00-12:00,12:00-00:00"
+        } else {
+            "00:00-00:00"
+        };
+        Config {
+            volume: 50,
+            quiet_hours: DailyHours::parse(hours),
+            quiet_volume: 10,
+            #[cfg(feature = "audio")]
+            sound_dir: None,
+        }
+    }
+
+    #[test]
+    fn cue_for_state() {
+        assert_eq!(Cue::for_state(AuthState::Idle), None);
+        assert_eq!(Cue::for_state(AuthState::Pending), Some(Cue::Pending));
+        assert_eq!(Cue::for_state(AuthState::Valid), Some(Cue::Valid));
+        assert_eq!(Cue::for_state(AuthState::NFCError), Some(Cue::NfcError));
+        assert_eq!(
+            Cue::for_state(AuthState::DoorHWNotReady),
+            Some(Cue::DoorNotReady)
+        );
+        assert_eq!(
+            Cue::for_state(AuthState::OutsideHours { next_open: None }),
+            Some(Cue::OutsideHours)
+        );
+    }
+
+    #[test]
+    fn quiet_hours_lower_the_volume() {
+        assert!((config(false).volume_now() - 0.5).abs() < f32::EPSILON);
+        assert!((config(true).volume_now() - 0.1).abs() < f32::EPSILON);
+
+        let no_quiet_hours = Config {
+            quiet_hours: None,
+            ..config(true)
+        };
+        assert!((no_quiet_hours.volume_now() - 0.5).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn muted_cues_are_not_played() {
+        let mut backend = Null;
+        assert_eq!(
+            play_state(&mut backend, &config(false), AuthState::Valid),
+            Some(Cue::Valid)
+        );
+        assert_eq!(
+            play_state(&mut backend, &config(false), AuthState::Idle),
+            None
+        );
+
+        let muted = Config {
+            quiet_volume: 0,
+            ..config(true)
+        };
+        assert_eq!(play_state(&mut backend, &muted, AuthState::Valid), None);
+    }
+}
@@ -0,0 +1,136 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use rodio::source::{SineWave, Zero};
+use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink, Source};
+use tracing::warn;
+
+use crate::audio::{AudioBackend, Cue};
+
+/// Custom sound formats, in the order they are looked for
+const EXTENSIONS: [&str; 2] = ["wav", "ogg"];
+const SAMPLE_RATE: u32 = 48_000;
+/// Built-in tones are played quieter than full scale, which is harsh on small speakers
+const TONE_AMPLITUDE: f32 = 0.4;
+
+/// Notes of a built-in sound, as a frequency in Hz (0 for a rest) and milliseconds
+fn tones(cue: Cue) -> &'static [(f32, u64)] {
+    match cue {
+        Cue::Pending => &[(1047.0, 60)],
+        // Rising, for success
+        Cue::Valid => &[
+            (659.0, 90),
+            (0.0, 30),
+            (880.0, 90),
+            (0.0, 30),
+            (1319.0, 180),
+        ],
+        // Falling, for refusals
+        Cue::Invalid => &[(392.0, 180), (0.0, 50), (262.0, 320)],
+        Cue::OutsideHours => &[
+            (523.0, 140),
+            (0.0, 50),
+            (440.0, 140),
+            (0.0, 50),
+            (349.0, 260),
+        ],
+        Cue::RateLimited => &[(440.0, 70), (0.0, 50), (440.0, 70), (0.0, 50), (440.0, 70)],
+        Cue::Lockdown => &[(220.0, 600)],
+        // Flat, for faults that are not the visitor's doing
+        Cue::NetError => &[(587.0, 120), (0.0, 80), (587.0, 120)],
+        Cue::NfcError => &[(784.0, 80), (0.0, 60), (784.0, 80)],
+        Cue::DoorNotReady => &[
+            (196.0, 150),
+            (0.0, 100),
+            (196.0, 150),
+            (0.0, 100),
+            (196.0, 150),
+        ],
+    }
+}
+
+pub struct Speaker {
+    // Sounds stop once the stream is dropped
+    _stream: OutputStream,
+    handle: OutputStreamHandle,
+    sound_dir: Option<PathBuf>,
+    /// The sound playing, stopped when replaced
+    sink: Option<Sink>,
+}
+
+impl Speaker {
+    /// Opens the default audio output
+    ///
+    /// # Errors
+    ///
+    /// Will error if there is no audio output
+    pub fn open(sound_dir: Option<PathBuf>) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let (stream, handle) = OutputStream::try_default()?;
+        Ok(Self {
+            _stream: stream,
+            handle,
+            sound_dir,
+            sink: None,
+        })
+    }
+
+    /// Queues the custom sound for a cue, returning whether there is one
+    fn append_custom(&self, sink: &Sink, cue: Cue) -> bool {
+        let Some(dir) = &self.sound_dir else {
+            return false;
+        };
+        let Some(path) = EXTENSIONS
+            .iter()
+            .map(|extension| dir.join(format!("{}.{extension}", cue.name())))
+            .find(|path| path.exists())
+        else {
+            return false;
+        };
+        match File::open(&path)
+            .map_err(Box::<dyn Error + Send + Sync>::from)
+            .and_then(|file| Ok(Decoder::new(BufReader::new(file))?))
+        {
+            Ok(sound) => {
+                sink.append(sound);
+                true
+            }
+            Err(e) => {
+                warn!(path = %path.display(), error = %e, "failed to load sound, using the built-in one");
+                false
+            }
+        }
+    }
+}
+
+impl AudioBackend for Speaker {
+    fn play(&mut self, cue: Cue, volume: f32) {
+        let sink = match Sink::try_new(&self.handle) {
+            Ok(sink) => sink,
+            Err(e) => {
+                warn!(error = %e, "failed to play sound");
+                return;
+            }
+        };
+        sink.set_volume(volume);
+
+        if !self.append_custom(&sink, cue) {
+            for &(frequency, millis) in tones(cue) {
+                let length = Duration::from_millis(millis);
+                if frequency > 0.0 {
+                    sink.append(
+                        SineWave::new(frequency)
+                            .take_duration(length)
+                            .amplify(TONE_AMPLITUDE),
+                    );
+                } else {
+                    sink.append(Zero::<f32>::new(1, SAMPLE_RATE).take_duration(length));
+                }
+            }
+        }
+        // Dropping the previous sink cuts off its sound, so outcomes are never queued behind it
+        self.sink = Some(sink);
+    }
+}
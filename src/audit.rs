@@ -0,0 +1,96 @@
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::sync::{LazyLock, Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tracing::error;
+
+/// Audit log location used when `AUDIT_LOG_PATH` is unset
+pub const DEFAULT_AUDIT_LOG_PATH: &str = "audit.log";
+
+/// Something that happened at the door which should be kept on record
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    /// A passport scan and its outcome
+    Scan {
+        passport_id: Option<i32>,
+        result: String,
+        /// File name of the photo taken during the scan, if any
+        photo: Option<String>,
+    },
+    /// An actuation of the door module, and where the open request came from
+    DoorOpen { success: bool, source: String },
+    /// Open house mode starting, or stopping if `until` is `None`
+    OpenHouse { until: Option<i64>, source: String },
+    /// Lockdown starting or being lifted
+    Lockdown { active: bool, source: String },
+    /// An open request refused without touching the door module
+    OpenRefused { reason: String, source: String },
+    /// A camera preview stream starting or stopping
+    CameraPreview { active: bool, source: String },
+    /// A guest pass being created, revoked, redeemed or expiring
+    ///
+    /// Rejected redemptions have no `pass_id` or `label`.
+    GuestPass {
+        pass_id: Option<String>,
+        label: Option<String>,
+        action: String,
+        source: String,
+    },
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    timestamp: u64,
+    #[serde(flatten)]
+    event: &'a AuditEvent,
+}
+
+static LOG: LazyLock<Mutex<Option<BufWriter<File>>>> = LazyLock::new(|| {
+    let path = env::var("AUDIT_LOG_PATH").unwrap_or_else(|_| DEFAULT_AUDIT_LOG_PATH.into());
+    let file = OpenOptions::new().create(true).append(true).open(&path);
+    match file {
+        Ok(file) => Mutex::new(Some(BufWriter::new(file))),
+        Err(e) => {
+            error!(path, error = %e, "failed to open audit log, audit events will be dropped");
+            Mutex::new(None)
+        }
+    }
+});
+
+/// Appends an event to the audit log as a JSON line
+pub fn record(event: &AuditEvent) {
+    let record = Record {
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+        event,
+    };
+
+    let mut log = LOG.lock().unwrap_or_else(PoisonError::into_inner);
+    let Some(writer) = log.as_mut() else {
+        return;
+    };
+    let res = serde_json::to_writer(&mut *writer, &record)
+        .map_err(std::io::Error::from)
+        .and_then(|()| writer.write_all(b"\n"))
+        .and_then(|()| writer.flush());
+    if let Err(e) = res {
+        error!(error = %e, "failed to write audit log");
+    }
+}
+
+/// Flushes the audit log and waits for it to reach the disk
+pub fn flush() {
+    let mut log = LOG.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(writer) = log.as_mut() {
+        let res = writer.flush().and_then(|()| writer.get_ref().sync_all());
+        if let Err(e) = res {
+            error!(error = %e, "failed to flush audit log");
+        }
+    }
+}
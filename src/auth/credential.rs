@@ -0,0 +1,86 @@
+//! Signed credentials carried on passports, verified without the ID server
+//!
+//! A credential is stored as an extra NDEF text record of the form
+//! `<payload>.<signature>`, both base64url encoded without padding. The payload
+//! is JSON, signed with Ed25519 by a key listed in `CREDENTIAL_PUBLIC_KEYS`.
+
+use std::env;
+use std::error::Error;
+
+use base64::Engine;
+use base64::engine::general_purpose;
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::keys;
+use crate::policy::Role;
+
+type Result<T> = std::result::Result<T, Box<dyn Error + Send + Sync>>;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Credential {
+    /// Passport the credential was issued for
+    pub id: i32,
+    /// Holder's name
+    pub name: String,
+    pub role: Role,
+    /// When the credential stops being accepted, as a Unix timestamp
+    pub exp: i64,
+}
+
+/// When passports with a valid credential are also checked against the ID server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdServerCheck {
+    /// Credentials alone are enough
+    Never,
+    /// Checked as a second factor, but skipped if the ID server is unreachable
+    Online,
+    /// Checked as a second factor, refusing entry if the ID server is unreachable
+    Always,
+}
+
+impl IdServerCheck {
+    /// Reads `ID_SERVER_CHECK`, which defaults to `never`
+    #[must_use]
+    pub fn from_env() -> Self {
+        match env::var("ID_SERVER_CHECK").as_deref() {
+            Err(_) | Ok("never") => Self::Never,
+            Ok("online") => Self::Online,
+            Ok("always") => Self::Always,
+            Ok(other) => {
+                warn!(
+                    value = other,
+                    "unknown ID_SERVER_CHECK, checking every passport"
+                );
+                Self::Always
+            }
+        }
+    }
+}
+
+/// Verifies a credential read from passport `passport_id` at Unix time `now`
+///
+/// # Errors
+///
+/// Will error if the credential is malformed, was not signed by a trusted key,
+/// was issued for another passport, or has expired
+pub fn verify(record: &str, passport_id: i32, now: i64) -> Result<Credential> {
+    let (payload, signature) = record
+        .split_once('.')
+        .ok_or("credential is missing its signature")?;
+    let payload = general_purpose::URL_SAFE_NO_PAD.decode(payload)?;
+    keys::verify(
+        &keys::from_env("CREDENTIAL_PUBLIC_KEYS")?,
+        &payload,
+        &general_purpose::URL_SAFE_NO_PAD.decode(signature)?,
+    )?;
+
+    let credential: Credential = serde_json::from_slice(&payload)?;
+    if credential.id != passport_id {
+        return Err(format!("credential was issued for passport {}", credential.id).into());
+    }
+    if credential.exp <= now {
+        return Err("credential has expired".into());
+    }
+    Ok(credential)
+}
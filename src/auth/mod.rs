@@ -1,19 +1,64 @@
 #[cfg(feature = "nfc_reader")]
+mod credential;
+#[cfg(feature = "nfc_reader")]
+mod ratelimit;
+
+use std::time::Instant;
+#[cfg(feature = "nfc_reader")]
 use std::{thread, time::Duration};
 
+#[cfg(feature = "nfc_reader")]
+use chrono::Utc;
 use reqwest::{Error, StatusCode};
-use tokio::sync::mpsc::UnboundedSender;
+#[cfg(feature = "nfc_reader")]
+use tracing::{error, info, warn};
 
 #[cfg(feature = "nfc_reader")]
 use nfc1::Error as NFC1Error;
 
+#[cfg(feature = "nfc_reader")]
+use crate::alerts::{self, AlertKind};
+#[cfg(feature = "nfc_reader")]
+use crate::audit::{self, AuditEvent};
+#[cfg(feature = "nfc_reader")]
+use crate::camera::{motion, tap_photos};
+#[cfg(feature = "nfc_reader")]
 use crate::enums::AuthState;
+#[cfg(feature = "nfc_reader")]
+use crate::events::{self, Event};
+#[cfg(feature = "nfc_reader")]
+use crate::hardware::door::{OpenRequest, OpenSource};
+use crate::health::{self, Status, Subsystem};
+use crate::metrics;
+#[cfg(feature = "nfc_reader")]
+use crate::mode;
+#[cfg(feature = "nfc_reader")]
+use crate::revocation;
+#[cfg(feature = "nfc_reader")]
+use crate::shutdown;
+
+#[cfg(feature = "nfc_reader")]
+use crate::hardware::nfc::{NFCReader, structs::PassportData, tag_uid};
+#[cfg(feature = "nfc_reader")]
+use crate::policy::{AccessPolicy, Decision, Role};
 
 #[cfg(feature = "nfc_reader")]
-use crate::hardware::nfc::NFCReader;
+use self::credential::{Credential, IdServerCheck};
+#[cfg(feature = "nfc_reader")]
+use self::ratelimit::{Limit, RateLimiter};
+#[cfg(feature = "nfc_reader")]
+use AuthState::{
+    DoorHWNotReady, Idle, Invalid, Lockdown, NFCError, NetError, OutsideHours, Pending,
+    RateLimited, Valid,
+};
 
 #[cfg(feature = "nfc_reader")]
-use AuthState::{Idle, Invalid, NFCError, NetError, Pending, Valid};
+const NFC_RETRY_DELAY: Duration = Duration::from_secs(10);
+#[cfg(feature = "nfc_reader")]
+const IDLE_POLL_DELAY: Duration = Duration::from_millis(300);
+/// Delay between polls while someone is at the door, so their tap is read sooner
+#[cfg(feature = "nfc_reader")]
+const PRESENT_POLL_DELAY: Duration = Duration::from_millis(50);
 
 #[cfg(feature = "nfc_reader")]
 /// Authentication thread
@@ -21,63 +66,364 @@ use AuthState::{Idle, Invalid, NFCError, NetError, Pending, Valid};
 /// # Panics
 ///
 /// Will panic if the NFC reader cannot be initialized
-pub fn auth_entry(gui_sender: &UnboundedSender<AuthState>, opener_tx: &UnboundedSender<()>) {
-    let nfc_reader_res: Result<NFCReader, NFC1Error> = NFCReader::new();
+pub fn auth_entry() {
+    let mut nfc_reader = loop {
+        if shutdown::is_triggered() {
+            return;
+        }
+        match NFCReader::new() {
+            Ok(nfc_reader) => break nfc_reader,
+            // Keep retrying so a reader that shows up later is picked up
+            Err(NFC1Error::NoDeviceFound) => {
+                health::set_status(
+                    Subsystem::Reader,
+                    Status::Degraded(String::from("no NFC device found")),
+                );
+                thread::sleep(NFC_RETRY_DELAY);
+            }
+            Err(e) => panic!("Failed to initialize NFC reader: {e:?}"),
+        }
+    };
+    // Also reports a broken policy before the first tap
+    load_policy();
+    let mut limiter = RateLimiter::from_env();
 
-    // Early return if NFC device is not available
-    if nfc_reader_res
-        .as_ref()
-        .is_err_and(|e| e == &NFC1Error::NoDeviceFound)
-    {
-        return;
-    }
-
-    let mut nfc_reader = nfc_reader_res.expect("Failed to initialize NFC reader");
-
-    loop {
-        if let Ok(target) = nfc_reader.poll() {
-            let _ = gui_sender.send(Pending);
-
-            if let Ok(data) = nfc_reader.read(target) {
-                let res = check_passport_validity(data.id, &data.secret);
-                thread::sleep(Duration::from_millis(2500));
-
-                match res {
-                    Ok(verified) => {
-                        let _ = gui_sender.send(if verified { Valid } else { Invalid });
-                        if verified {
-                            println!("Passport successfully validated, sending open command...");
-                            match opener_tx.send(()) {
-                                Ok(()) => {}
-                                Err(e) => {
-                                    eprintln!("auth: failed to send open command: {e:?}");
-                                }
-                            }
-                        }
-                    }
-                    Err(_) => {
-                        let _ = gui_sender.send(NetError);
-                    }
-                }
-            } else {
-                thread::sleep(Duration::from_millis(2500));
-                let _ = gui_sender.send(NFCError);
+    // Polls return regularly, so the reader beats and checks for shutdown while
+    // the door is quiet. A scan in progress is finished before checking again
+    while !shutdown::is_triggered() {
+        health::beat(Subsystem::Reader);
+
+        let target = match nfc_reader.poll() {
+            Ok(target) => target,
+            Err(e) => {
+                warn!(error = ?e, "failed to poll NFC reader");
+                None
+            }
+        };
+        if let Some(target) = target {
+            events::publish(Event::Auth(Pending));
+            motion::answered();
+            let photo = tap_photos::start_capture();
+
+            let uid = tag_uid(&target);
+            let data = nfc_reader.read(target).ok();
+            let (state, result) = handle_tap(&mut limiter, uid.as_deref(), data.as_ref());
+            let passport_id = data.as_ref().map(|data| data.id);
+            thread::sleep(Duration::from_millis(2500));
+
+            events::publish(Event::Auth(state));
+            if state == Valid {
+                println!("Passport successfully validated, sending open command...");
+                let actor = passport_id.map(|id| format!("passport:{id}"));
+                events::request_open(OpenRequest::new(OpenSource::Tap, actor, result));
             }
 
+            // Waiting for the photo must not hold up the door
+            let photo = photo.and_then(|photo| photo.finish(state == Valid, passport_id, result));
+            record_scan(passport_id, result, photo);
+
             thread::sleep(Duration::from_secs(5));
 
-            let _ = gui_sender.send(Idle);
+            events::publish(Event::Auth(Idle));
+        }
+
+        thread::sleep(if motion::is_present() {
+            PRESENT_POLL_DELAY
+        } else {
+            IDLE_POLL_DELAY
+        });
+    }
+
+    // Dropping the reader closes the NFC device and frees its context
+    drop(nfc_reader);
+    info!("NFC reader closed");
+}
+
+/// Decides the outcome of a tap, along with the scan result to record
+///
+/// `uid` is the tag's UID, if it reports one, and `data` is `None` if the tag
+/// could not be read as a passport.
+#[cfg(feature = "nfc_reader")]
+fn handle_tap(
+    limiter: &mut RateLimiter,
+    uid: Option<&str>,
+    data: Option<&PassportData>,
+) -> (AuthState, &'static str) {
+    let policy = load_policy();
+    let passport_id = data.map(|data| data.id);
+
+    // Revoked passports are refused before anything else, without asking the ID server
+    if let Some(passport_id) = passport_id
+        && revocation::is_revoked(passport_id)
+    {
+        return (Invalid, "revoked");
+    }
+
+    // Tags without a UID are tracked by the passport they claim to be
+    let tag =
+        |data: &PassportData| uid.map_or_else(|| format!("passport:{}", data.id), str::to_string);
+
+    if let Some(data) = data
+        && is_lockdown_tag(policy.as_ref(), data.id)
+    {
+        return toggle_lockdown(limiter, &tag(data), data, |data| {
+            check_passport_validity(data.id, &data.secret)
+        });
+    }
+
+    let state = if mode::is_locked_down() {
+        Lockdown
+    } else if mode::open_house().is_some() {
+        // Any tag opens the door, whether or not it can be read
+        return (Valid, "open_house");
+    } else if let Some(data) = data {
+        authorize_limited(limiter, &tag(data), data, policy.as_ref())
+    } else {
+        NFCError
+    };
+    (state, scan_result_label(state))
+}
+
+/// Starts or lifts lockdown from a lockdown tag, checked with `validate`
+///
+/// Starting lockdown must work while the ID server is unreachable, so only a
+/// rejected passport is refused. Lifting it needs the passport to be validated.
+/// Lockdown tags are rate limited like any other, so their secrets cannot be
+/// guessed at the door.
+#[cfg(feature = "nfc_reader")]
+fn toggle_lockdown(
+    limiter: &mut RateLimiter,
+    tag: &str,
+    data: &PassportData,
+    validate: impl FnOnce(&PassportData) -> Result<bool, Error>,
+) -> (AuthState, &'static str) {
+    if let Err(state) = check_limits(limiter, tag, data) {
+        return (state, scan_result_label(state));
+    }
+    let validity = validate(data);
+    if let Ok(valid) = &validity {
+        record_validity(limiter, tag, data, *valid);
+    }
+    let source = format!("tag:{}", data.id);
+
+    match (mode::is_locked_down(), validity) {
+        (_, Ok(false)) => (Invalid, "invalid"),
+        (true, Err(_)) => (NetError, "net_error"),
+        (true, Ok(true)) => {
+            mode::lift_lockdown(&source);
+            (Idle, "lockdown_lifted")
+        }
+        (false, _) => {
+            mode::start_lockdown(&source);
+            (Lockdown, "lockdown_started")
+        }
+    }
+}
+
+/// Authorizes a passport unless rate limiting refuses to check it
+#[cfg(feature = "nfc_reader")]
+fn authorize_limited(
+    limiter: &mut RateLimiter,
+    tag: &str,
+    data: &PassportData,
+    policy: Option<&AccessPolicy>,
+) -> AuthState {
+    if let Err(state) = check_limits(limiter, tag, data) {
+        return state;
+    }
+
+    let state = authorize(data, policy);
+    match state {
+        Invalid => record_validity(limiter, tag, data, false),
+        Valid | OutsideHours { .. } => record_validity(limiter, tag, data, true),
+        _ => {}
+    }
+    state
+}
+
+/// Counts a check of a passport, returning the state to show if rate limiting refuses it
+///
+/// Organizers are alerted when a limit trips.
+#[cfg(feature = "nfc_reader")]
+fn check_limits(
+    limiter: &mut RateLimiter,
+    tag: &str,
+    data: &PassportData,
+) -> Result<(), AuthState> {
+    if let Err(cooldown) = limiter.check(tag, data.id, Instant::now()) {
+        if cooldown.tripped {
+            let scope = match cooldown.limit {
+                Limit::Global => String::from("all tags"),
+                Limit::Passport => format!("passport {}", data.id),
+                Limit::Lockout => format!("tag {tag}"),
+            };
+            alerts::raise(
+                AlertKind::RateLimited,
+                format!(
+                    "Rate limit reached for {scope}, refusing scans for {}s",
+                    cooldown.remaining.as_secs()
+                ),
+            );
+        }
+        let remaining =
+            i64::try_from(cooldown.remaining.as_millis().div_ceil(1000)).unwrap_or(i64::MAX);
+        return Err(RateLimited {
+            retry_at: Utc::now().timestamp().saturating_add(remaining),
+        });
+    }
+    Ok(())
+}
+
+/// Records whether the ID server accepted a passport
+///
+/// Organizers are alerted when a tag gets locked out.
+#[cfg(feature = "nfc_reader")]
+fn record_validity(limiter: &mut RateLimiter, tag: &str, data: &PassportData, valid: bool) {
+    if valid {
+        limiter.record_valid(tag);
+    } else if let Some(lockout) = limiter.record_invalid(tag, Instant::now()) {
+        alerts::raise(
+            AlertKind::Lockout,
+            format!(
+                "Tag {tag} claiming passport {} locked out for {}s after repeated invalid scans",
+                data.id,
+                lockout.as_secs()
+            ),
+        );
+    }
+}
+
+/// Verifies the passport's credential, if it carries one
+///
+/// A credential that fails verification is logged and ignored, leaving the
+/// passport to be checked against the ID server like one without a credential.
+#[cfg(feature = "nfc_reader")]
+fn verify_credential(data: &PassportData) -> Option<Credential> {
+    let record = data.credential.as_deref()?;
+    match credential::verify(record, data.id, Utc::now().timestamp()) {
+        Ok(credential) => {
+            info!(
+                passport_id = data.id,
+                name = credential.name,
+                "verified passport credential"
+            );
+            Some(credential)
         }
+        Err(e) => {
+            warn!(passport_id = data.id, error = %e, "ignoring passport credential");
+            None
+        }
+    }
+}
 
-        thread::sleep(Duration::from_millis(300));
+/// Decides the outcome of a successfully read passport
+///
+/// Passports with a valid credential are only checked against the ID server as
+/// configured by `ID_SERVER_CHECK`.
+#[cfg(feature = "nfc_reader")]
+fn authorize(data: &PassportData, policy: Option<&AccessPolicy>) -> AuthState {
+    let credential = verify_credential(data);
+    let id_server_check = if credential.is_some() {
+        IdServerCheck::from_env()
+    } else {
+        IdServerCheck::Always
+    };
+
+    if id_server_check != IdServerCheck::Never {
+        match check_passport_validity(data.id, &data.secret) {
+            Ok(true) => {}
+            Ok(false) => return Invalid,
+            Err(e) if id_server_check == IdServerCheck::Online => {
+                warn!(passport_id = data.id, error = %e, "ID server unreachable, trusting credential");
+            }
+            Err(_) => return NetError,
+        }
+    }
+
+    let decision = policy.map_or(Decision::Allow, |policy| {
+        let role = match (policy.role_of(data.id), &credential) {
+            (Role::Member, Some(credential)) => credential.role,
+            (role, _) => role,
+        };
+        policy.evaluate(role)
+    });
+    match decision {
+        Decision::Allow => Valid,
+        Decision::Closed { next_open } => OutsideHours { next_open },
     }
 }
 
+/// Loads the access policy, letting everyone in if it is unset or broken
+///
+/// A broken policy leaves the reader degraded until it loads again.
+#[cfg(feature = "nfc_reader")]
+fn load_policy() -> Option<AccessPolicy> {
+    // Reloaded on every scan so schedule and calendar edits apply immediately
+    match AccessPolicy::from_env() {
+        Ok(policy) => {
+            health::set_status(Subsystem::Reader, Status::Ready);
+            policy
+        }
+        Err(e) => {
+            error!(error = %e, "failed to load access policy, allowing entry");
+            health::set_status(
+                Subsystem::Reader,
+                Status::Degraded(String::from("access policy failed to load")),
+            );
+            None
+        }
+    }
+}
+
+/// Whether tapping this passport starts or lifts lockdown
+///
+/// Without a loaded policy the tags are read on their own, so lockdown can
+/// still be started from a tag while the rest of the policy is broken.
+#[cfg(feature = "nfc_reader")]
+fn is_lockdown_tag(policy: Option<&AccessPolicy>, passport_id: i32) -> bool {
+    match policy {
+        Some(policy) => policy.is_lockdown_tag(passport_id),
+        None => {
+            AccessPolicy::lockdown_tags_from_env().is_ok_and(|tags| tags.contains(&passport_id))
+        }
+    }
+}
+
+#[cfg(feature = "nfc_reader")]
+fn scan_result_label(state: AuthState) -> &'static str {
+    match state {
+        Valid => "valid",
+        Invalid => "invalid",
+        NetError => "net_error",
+        NFCError => "nfc_error",
+        OutsideHours { .. } => "outside_hours",
+        Lockdown => "lockdown",
+        RateLimited { .. } => "rate_limited",
+        Idle | Pending | DoorHWNotReady => "other",
+    }
+}
+
+#[cfg(feature = "nfc_reader")]
+fn record_scan(passport_id: Option<i32>, result: &str, photo: Option<String>) {
+    metrics::record_scan(result);
+    audit::record(&AuditEvent::Scan {
+        passport_id,
+        result: result.to_string(),
+        photo,
+    });
+    events::publish(Event::Scan {
+        passport_id,
+        result: result.to_string(),
+    });
+}
+
 /// Dummy authentication module
 ///
 /// Enabled if NFC feature is disabled
 #[cfg(not(feature = "nfc_reader"))]
-pub fn auth_entry(_gui_sender: &UnboundedSender<AuthState>, _opener_tx: &UnboundedSender<()>) {}
+pub fn auth_entry() {
+    health::set_status(Subsystem::Reader, Status::Disabled);
+}
 
 /// Checks for passport validity
 ///
@@ -89,6 +435,7 @@ pub fn auth_entry(_gui_sender: &UnboundedSender<AuthState>, _opener_tx: &Unbound
 ///
 /// Will panic if the request does not send a valid error string
 pub fn check_passport_validity(id: i32, secret: &str) -> Result<bool, Error> {
+    let started = Instant::now();
     let client = reqwest::blocking::Client::new();
     let res = client
         .post("https://id.purduehackers.com/api/door")
@@ -99,13 +446,56 @@ pub fn check_passport_validity(id: i32, secret: &str) -> Result<bool, Error> {
     match res {
         Ok(res) => {
             if res.status() == StatusCode::OK {
+                metrics::record_id_api_request("valid", started.elapsed());
                 Ok(true)
             } else {
+                metrics::record_id_api_request("rejected", started.elapsed());
                 println!("Got error status: {}", res.status());
                 println!("Got error text: {}", res.text().unwrap());
                 Ok(false)
             }
         }
-        Err(e) => Err(e),
+        Err(e) => {
+            metrics::record_id_api_request("error", started.elapsed());
+            Err(e)
+        }
+    }
+}
+
+#[cfg(all(test, feature = "nfc_reader"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bad_lockdown_tag_taps_are_rate_limited() {
+        let mut limiter = RateLimiter::new(20, 10, 3);
+        let data = PassportData {
+            id: 42,
+            secret: String::from("guess"),
+            credential: None,
+        };
+
+        let mut checks = 0;
+        let mut results = Vec::new();
+        for _ in 0..5 {
+            let (_, result) = toggle_lockdown(&mut limiter, "04a1b2c3", &data, |_| {
+                checks += 1;
+                Ok(false)
+            });
+            results.push(result);
+        }
+
+        // The tag is locked out after the third rejection, without asking the ID server again
+        assert_eq!(
+            results,
+            [
+                "invalid",
+                "invalid",
+                "invalid",
+                "rate_limited",
+                "rate_limited"
+            ]
+        );
+        assert_eq!(checks, 3);
     }
 }
@@ -0,0 +1,177 @@
+//! Rate limiting of passport checks against the ID server
+//!
+//! Checks are limited globally and per passport over a sliding minute. Tags
+//! that keep failing validation are locked out for exponentially longer.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::env;
+use std::time::{Duration, Instant};
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Checks allowed per minute across all tags when `RATE_LIMIT_GLOBAL_PER_MINUTE` is unset
+pub const DEFAULT_GLOBAL_PER_MINUTE: usize = 20;
+/// Checks allowed per minute for one passport when `RATE_LIMIT_PASSPORT_PER_MINUTE` is unset
+pub const DEFAULT_PASSPORT_PER_MINUTE: usize = 4;
+/// Invalid scans of one tag before it is locked out when `LOCKOUT_THRESHOLD` is unset
+pub const DEFAULT_LOCKOUT_THRESHOLD: u32 = 3;
+
+/// Length of the first lockout, doubled for each further invalid scan
+const LOCKOUT_BASE: Duration = Duration::from_secs(30);
+const LOCKOUT_MAX: Duration = Duration::from_secs(60 * 60);
+/// Invalid scans are forgotten once a tag has not failed for this long
+const FAILURES_FORGOTTEN_AFTER: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Global,
+    Passport,
+    Lockout,
+}
+
+/// Why a check was refused, and for how long
+#[derive(Debug, Clone, Copy)]
+pub struct Cooldown {
+    pub limit: Limit,
+    pub remaining: Duration,
+    /// Whether this is the first refusal since the limit was last passed
+    pub tripped: bool,
+}
+
+struct Failures {
+    count: u32,
+    last: Instant,
+    locked_until: Option<Instant>,
+}
+
+pub struct RateLimiter {
+    global_per_minute: usize,
+    passport_per_minute: usize,
+    lockout_threshold: u32,
+    global: VecDeque<Instant>,
+    passports: HashMap<i32, VecDeque<Instant>>,
+    failures: HashMap<String, Failures>,
+    global_tripped: bool,
+    tripped_passports: HashSet<i32>,
+}
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Drops attempts which have left the window, returning when the oldest remaining one does
+fn prune(attempts: &mut VecDeque<Instant>, now: Instant) -> Option<Instant> {
+    while attempts
+        .front()
+        .is_some_and(|&at| now.duration_since(at) >= WINDOW)
+    {
+        attempts.pop_front();
+    }
+    attempts.front().map(|&at| at + WINDOW)
+}
+
+impl RateLimiter {
+    #[must_use]
+    pub fn new(
+        global_per_minute: usize,
+        passport_per_minute: usize,
+        lockout_threshold: u32,
+    ) -> Self {
+        Self {
+            global_per_minute,
+            passport_per_minute,
+            lockout_threshold,
+            global: VecDeque::new(),
+            passports: HashMap::new(),
+            failures: HashMap::new(),
+            global_tripped: false,
+            tripped_passports: HashSet::new(),
+        }
+    }
+
+    /// Reads limits from the environment, falling back to the defaults
+    #[must_use]
+    pub fn from_env() -> Self {
+        Self::new(
+            env_or("RATE_LIMIT_GLOBAL_PER_MINUTE", DEFAULT_GLOBAL_PER_MINUTE),
+            env_or(
+                "RATE_LIMIT_PASSPORT_PER_MINUTE",
+                DEFAULT_PASSPORT_PER_MINUTE,
+            ),
+            env_or("LOCKOUT_THRESHOLD", DEFAULT_LOCKOUT_THRESHOLD),
+        )
+    }
+
+    /// Counts a check of `passport_id` read from tag `tag`, unless a limit refuses it
+    ///
+    /// # Errors
+    ///
+    /// Will error with the cooldown if the tag is locked out or a limit is reached
+    pub fn check(&mut self, tag: &str, passport_id: i32, now: Instant) -> Result<(), Cooldown> {
+        self.failures
+            .retain(|_, f| now.duration_since(f.last) < FAILURES_FORGOTTEN_AFTER);
+        if let Some(until) = self.failures.get(tag).and_then(|f| f.locked_until)
+            && until > now
+        {
+            return Err(Cooldown {
+                limit: Limit::Lockout,
+                remaining: until - now,
+                tripped: false,
+            });
+        }
+
+        let global_free_at = prune(&mut self.global, now);
+        if self.global.len() >= self.global_per_minute {
+            let tripped = !std::mem::replace(&mut self.global_tripped, true);
+            return Err(Cooldown {
+                limit: Limit::Global,
+                remaining: global_free_at.map_or(WINDOW, |at| at - now),
+                tripped,
+            });
+        }
+
+        let attempts = self.passports.entry(passport_id).or_default();
+        let passport_free_at = prune(attempts, now);
+        if attempts.len() >= self.passport_per_minute {
+            return Err(Cooldown {
+                limit: Limit::Passport,
+                remaining: passport_free_at.map_or(WINDOW, |at| at - now),
+                tripped: self.tripped_passports.insert(passport_id),
+            });
+        }
+
+        attempts.push_back(now);
+        self.global.push_back(now);
+        self.global_tripped = false;
+        self.tripped_passports.remove(&passport_id);
+        self.passports
+            .retain(|_, attempts| prune(attempts, now).is_some());
+        Ok(())
+    }
+
+    /// Records a scan rejected by the ID server, returning the lockout it starts, if any
+    pub fn record_invalid(&mut self, tag: &str, now: Instant) -> Option<Duration> {
+        let failures = self.failures.entry(tag.to_string()).or_insert(Failures {
+            count: 0,
+            last: now,
+            locked_until: None,
+        });
+        failures.count += 1;
+        failures.last = now;
+
+        let excess = failures.count.checked_sub(self.lockout_threshold)?;
+        let lockout = LOCKOUT_BASE
+            .checked_mul(2u32.saturating_pow(excess))
+            .map_or(LOCKOUT_MAX, |lockout| lockout.min(LOCKOUT_MAX));
+        failures.locked_until = Some(now + lockout);
+        Some(lockout)
+    }
+
+    /// Clears the invalid scans of a tag which has just been validated
+    pub fn record_valid(&mut self, tag: &str) {
+        self.failures.remove(tag);
+    }
+}
@@ -0,0 +1,201 @@
+//! Encoding camera frames into photos small enough to send quickly
+//!
+//! Photos are configured with:
+//!
+//! - `PHOTO_FORMAT`: `jpeg` (the default), `webp` (lossless) or `avif`
+//! - `PHOTO_QUALITY`: 1 to 100, for JPEG and AVIF (default 80)
+//! - `PHOTO_MAX_DIMENSION`: longest side in pixels, larger frames are
+//!   downscaled (default 1280)
+//! - `PHOTO_MAX_BYTES`: byte budget, met by lowering the quality and then the
+//!   size (default 262144, `0` for no budget)
+
+use std::env;
+use std::error::Error;
+
+use base64::Engine;
+use base64::engine::general_purpose;
+use image::codecs::avif::AvifEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::{self, FilterType};
+use image::{ExtendedColorType, ImageEncoder, RgbImage};
+use tracing::warn;
+
+type Result<T> = std::result::Result<T, Box<dyn Error + Send + Sync>>;
+
+/// Quality is not lowered below this to meet the byte budget
+const MIN_QUALITY: u8 = 30;
+/// How much the quality drops on each attempt to meet the byte budget
+const QUALITY_STEP: u8 = 15;
+/// Photos are not downscaled below this to meet the byte budget
+const MIN_DIMENSION: u32 = 160;
+/// AVIF encoder speed, from 1 (slowest) to 10, fast enough for a Pi
+const AVIF_SPEED: u8 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Jpeg,
+    WebP,
+    Avif,
+}
+
+impl Format {
+    pub const ALL: [Self; 3] = [Self::Jpeg, Self::WebP, Self::Avif];
+
+    #[must_use]
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Jpeg => "image/jpeg",
+            Self::WebP => "image/webp",
+            Self::Avif => "image/avif",
+        }
+    }
+
+    #[must_use]
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpg",
+            Self::WebP => "webp",
+            Self::Avif => "avif",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    pub format: Format,
+    pub quality: u8,
+    pub max_dimension: u32,
+    /// `None` for no budget
+    pub max_bytes: Option<usize>,
+}
+
+impl Config {
+    /// Reads the photo configuration, warning about and ignoring invalid values
+    #[must_use]
+    pub fn from_env() -> Self {
+        let format = match env::var("PHOTO_FORMAT").as_deref() {
+            Err(_) | Ok("jpeg") => Format::Jpeg,
+            Ok("webp") => Format::WebP,
+            Ok("avif") => Format::Avif,
+            Ok(other) => {
+                warn!(value = other, "unknown PHOTO_FORMAT, using jpeg");
+                Format::Jpeg
+            }
+        };
+        let quality = env::var("PHOTO_QUALITY")
+            .ok()
+            .and_then(|quality| quality.parse().ok())
+            .filter(|quality| (1..=100).contains(quality))
+            .unwrap_or(80);
+        let max_dimension = env::var("PHOTO_MAX_DIMENSION")
+            .ok()
+            .and_then(|dimension| dimension.parse().ok())
+            .filter(|dimension| *dimension > 0)
+            .unwrap_or(1280);
+        let max_bytes = env::var("PHOTO_MAX_BYTES")
+            .ok()
+            .and_then(|bytes| bytes.parse().ok())
+            .unwrap_or(256 * 1024);
+
+        Self {
+            format,
+            quality,
+            max_dimension,
+            max_bytes: (max_bytes > 0).then_some(max_bytes),
+        }
+    }
+}
+
+/// An encoded photo
+pub struct Photo {
+    pub bytes: Vec<u8>,
+    pub format: Format,
+}
+
+impl Photo {
+    /// The photo as a base64 data URL
+    #[must_use]
+    pub fn data_url(&self) -> String {
+        format!(
+            "data:{};base64,{}",
+            self.format.content_type(),
+            general_purpose::STANDARD.encode(&self.bytes)
+        )
+    }
+}
+
+/// Scales `image` down so its longest side is at most `max_dimension`
+fn downscale(image: &RgbImage, max_dimension: u32) -> RgbImage {
+    let (width, height) = image.dimensions();
+    let longest = width.max(height);
+    if longest <= max_dimension {
+        return image.clone();
+    }
+    let scale = |side: u32| {
+        u32::try_from(u64::from(side) * u64::from(max_dimension) / u64::from(longest))
+            .unwrap_or(max_dimension)
+            .max(1)
+    };
+    imageops::resize(image, scale(width), scale(height), FilterType::Triangle)
+}
+
+fn encode_once(image: &RgbImage, format: Format, quality: u8) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let (width, height) = image.dimensions();
+    match format {
+        Format::Jpeg => JpegEncoder::new_with_quality(&mut bytes, quality).write_image(
+            image,
+            width,
+            height,
+            ExtendedColorType::Rgb8,
+        )?,
+        Format::WebP => WebPEncoder::new_lossless(&mut bytes).write_image(
+            image,
+            width,
+            height,
+            ExtendedColorType::Rgb8,
+        )?,
+        Format::Avif => AvifEncoder::new_with_speed_quality(&mut bytes, AVIF_SPEED, quality)
+            .write_image(image, width, height, ExtendedColorType::Rgb8)?,
+    }
+    Ok(bytes)
+}
+
+/// Encodes a frame, lowering its quality and then its size to meet the byte budget
+///
+/// If the budget cannot be met, the smallest photo tried is returned.
+///
+/// # Errors
+///
+/// Will error if the frame cannot be encoded
+pub fn encode(frame: &RgbImage, config: &Config) -> Result<Photo> {
+    let mut image = downscale(frame, config.max_dimension);
+    let mut quality = config.quality;
+    loop {
+        let bytes = encode_once(&image, config.format, quality)?;
+        let Some(max_bytes) = config.max_bytes.filter(|max| bytes.len() > *max) else {
+            return Ok(Photo {
+                bytes,
+                format: config.format,
+            });
+        };
+
+        // Lossless WebP has no quality to lower
+        if config.format != Format::WebP && quality > MIN_QUALITY {
+            quality = quality.saturating_sub(QUALITY_STEP).max(MIN_QUALITY);
+        } else if image.width().max(image.height()) > MIN_DIMENSION {
+            let longest = image.width().max(image.height());
+            image = downscale(&image, (longest * 3 / 4).max(MIN_DIMENSION));
+        } else {
+            warn!(
+                bytes = bytes.len(),
+                max_bytes, "photo is over its byte budget at the lowest quality and size"
+            );
+            return Ok(Photo {
+                bytes,
+                format: config.format,
+            });
+        }
+    }
+}
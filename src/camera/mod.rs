@@ -0,0 +1,42 @@
+pub mod encoding;
+pub mod motion;
+pub mod preview;
+pub mod tap_photos;
+mod worker;
+
+pub use encoding::Photo;
+pub use worker::{camera_entry, latest_frame};
+
+use std::env;
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+use crate::metrics;
+
+/// Frames older than this are not used for photos
+const MAX_FRAME_AGE: Duration = Duration::from_secs(3);
+
+/// Whether `CAMERA_PRIVACY_MODE` turns the camera off entirely
+#[must_use]
+pub fn privacy_mode() -> bool {
+    env::var("CAMERA_PRIVACY_MODE").is_ok_and(|value| value == "true" || value == "1")
+}
+
+/// Captures a photo from the camera worker's latest frame, encoded as configured
+///
+/// # Errors
+///
+/// Will error if privacy mode is on, the camera has not produced a frame
+/// recently, or the frame cannot be encoded
+pub fn capture() -> Result<Photo, Box<dyn Error + Sync + Send>> {
+    if privacy_mode() {
+        return Err("camera is disabled by CAMERA_PRIVACY_MODE".into());
+    }
+    let started = Instant::now();
+    let res = latest_frame()
+        .filter(|frame| frame.captured_at.elapsed() < MAX_FRAME_AGE)
+        .ok_or_else(|| "camera has no recent frame".into())
+        .and_then(|frame| encoding::encode(&frame.image, &encoding::Config::from_env()));
+    metrics::record_photo_capture(res.is_ok(), started.elapsed());
+    res
+}
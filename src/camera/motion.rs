@@ -0,0 +1,242 @@
+//! Presence detection by comparing consecutive camera frames
+//!
+//! Each frame is reduced to a coarse grid of average brightness, and enough
+//! cells changing between frames counts as motion. Someone is present from
+//! the first motion until none has been seen for a while.
+//!
+//! Detection is configured with:
+//!
+//! - `MOTION_DETECTION`: `true` (the default) or `false`
+//! - `MOTION_SENSITIVITY`: percentage of the picture which has to change to
+//!   count as motion (default 3)
+//! - `PRESENCE_TIMEOUT_SECS`: seconds without motion before someone is
+//!   considered gone (default 10)
+//! - `PRESENCE_WAITING_ALERT_SECS`: alert organizers when someone has been
+//!   waiting this long without tapping in (unset for no alert)
+
+use std::env;
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use image::RgbImage;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, watch};
+use tracing::{info, warn};
+
+use crate::alerts::{self, AlertKind};
+
+/// Sensitivity used when `MOTION_SENSITIVITY` is unset
+pub const DEFAULT_SENSITIVITY: u8 = 3;
+/// How long someone stays present without motion when `PRESENCE_TIMEOUT_SECS` is unset
+pub const DEFAULT_PRESENCE_TIMEOUT: Duration = Duration::from_secs(10);
+
+const GRID_WIDTH: u32 = 32;
+const GRID_HEIGHT: u32 = 24;
+/// Only every this many pixels in each direction are sampled
+const SAMPLE_STEP: u32 = 4;
+/// Brightness change for a cell to count as changed, ignoring sensor noise
+const CELL_THRESHOLD: u8 = 12;
+/// Events kept for a slow subscriber before the oldest are dropped
+const EVENT_BUFFER: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceKind {
+    /// Motion was seen with nobody present
+    Arrived,
+    /// No motion has been seen for the presence timeout
+    Left,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct PresenceEvent {
+    pub kind: PresenceKind,
+    /// When the event happened, as a Unix timestamp
+    pub timestamp: i64,
+}
+
+static EVENTS: LazyLock<broadcast::Sender<PresenceEvent>> =
+    LazyLock::new(|| broadcast::channel(EVENT_BUFFER).0);
+static PRESENT: LazyLock<watch::Sender<bool>> = LazyLock::new(|| watch::Sender::new(false));
+/// Set when someone taps in or the door opens, so they are not waiting anymore
+static ANSWERED: AtomicBool = AtomicBool::new(false);
+
+/// Receives presence events from now on
+#[must_use]
+pub fn subscribe() -> broadcast::Receiver<PresenceEvent> {
+    EVENTS.subscribe()
+}
+
+/// Whether someone is at the door
+#[must_use]
+pub fn is_present() -> bool {
+    *PRESENT.borrow()
+}
+
+/// Marks whoever is at the door as dealt with, restarting the waiting alert
+pub fn answered() {
+    ANSWERED.store(true, Ordering::Relaxed);
+}
+
+fn publish(kind: PresenceKind) {
+    info!(?kind, "presence changed");
+    PRESENT.send_replace(kind == PresenceKind::Arrived);
+    // Failing to send only means nothing is listening
+    let _ = EVENTS.send(PresenceEvent {
+        kind,
+        timestamp: Utc::now().timestamp(),
+    });
+}
+
+/// Average brightness of each cell of a coarse grid over the frame
+fn brightness_grid(image: &RgbImage) -> Vec<u8> {
+    let (width, height) = image.dimensions();
+    let mut sums = vec![0u32; (GRID_WIDTH * GRID_HEIGHT) as usize];
+    let mut counts = vec![0u32; sums.len()];
+    for y in (0..height).step_by(SAMPLE_STEP as usize) {
+        let row = y * GRID_HEIGHT / height;
+        for x in (0..width).step_by(SAMPLE_STEP as usize) {
+            let cell = (row * GRID_WIDTH + x * GRID_WIDTH / width) as usize;
+            let [r, g, b] = image.get_pixel(x, y).0;
+            // Integer approximation of Rec. 601 luma
+            sums[cell] += (u32::from(r) * 299 + u32::from(g) * 587 + u32::from(b) * 114) / 1000;
+            counts[cell] += 1;
+        }
+    }
+    sums.iter()
+        .zip(&counts)
+        .map(|(sum, count)| u8::try_from(sum / (*count).max(1)).unwrap_or(u8::MAX))
+        .collect()
+}
+
+/// Frame differencing motion detector, fed every frame by the camera worker
+pub struct Detector {
+    enabled: bool,
+    /// Cells which have to change to count as motion
+    min_changed: usize,
+    timeout: Duration,
+    waiting_alert: Option<Duration>,
+    previous: Option<Vec<u8>>,
+    last_motion: Option<Instant>,
+    /// When whoever is present started waiting, and whether organizers were alerted
+    waiting_since: Option<(Instant, bool)>,
+}
+
+impl Detector {
+    /// Reads the detection configuration, warning about and ignoring invalid values
+    #[must_use]
+    pub fn from_env() -> Self {
+        let enabled =
+            env::var("MOTION_DETECTION").map_or(true, |value| value != "false" && value != "0");
+        let sensitivity = env::var("MOTION_SENSITIVITY")
+            .ok()
+            .and_then(|sensitivity| {
+                let parsed = sensitivity
+                    .parse()
+                    .ok()
+                    .filter(|percent| (1..=100).contains(percent));
+                if parsed.is_none() {
+                    warn!(
+                        value = sensitivity,
+                        "invalid MOTION_SENSITIVITY, using {DEFAULT_SENSITIVITY}"
+                    );
+                }
+                parsed
+            })
+            .unwrap_or(DEFAULT_SENSITIVITY);
+        let timeout = env::var("PRESENCE_TIMEOUT_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .map_or(DEFAULT_PRESENCE_TIMEOUT, Duration::from_secs);
+        let waiting_alert = env::var("PRESENCE_WAITING_ALERT_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs);
+
+        let cells = (GRID_WIDTH * GRID_HEIGHT) as usize;
+        Self {
+            enabled,
+            min_changed: (cells * usize::from(sensitivity)).div_ceil(100),
+            timeout,
+            waiting_alert,
+            previous: None,
+            last_motion: None,
+            waiting_since: None,
+        }
+    }
+
+    /// Compares a frame with the previous one, publishing presence changes
+    pub fn observe(&mut self, image: &RgbImage, now: Instant) {
+        if !self.enabled {
+            return;
+        }
+
+        let grid = brightness_grid(image);
+        let moved = self.previous.as_ref().is_some_and(|previous| {
+            let changed = previous
+                .iter()
+                .zip(&grid)
+                .filter(|(before, after)| before.abs_diff(**after) > CELL_THRESHOLD)
+                .count();
+            changed >= self.min_changed
+        });
+        self.previous = Some(grid);
+
+        if moved {
+            if self.last_motion.is_none() {
+                self.waiting_since = Some((now, false));
+                publish(PresenceKind::Arrived);
+            }
+            self.last_motion = Some(now);
+        } else if self
+            .last_motion
+            .is_some_and(|last| now.duration_since(last) >= self.timeout)
+        {
+            self.leave();
+            return;
+        }
+
+        self.check_waiting(now);
+    }
+
+    /// Alerts organizers once whoever is present has waited too long
+    fn check_waiting(&mut self, now: Instant) {
+        if ANSWERED.swap(false, Ordering::Relaxed) && self.waiting_since.is_some() {
+            self.waiting_since = Some((now, false));
+        }
+        let (Some(threshold), Some((since, alerted))) =
+            (self.waiting_alert, &mut self.waiting_since)
+        else {
+            return;
+        };
+        if !*alerted && now.duration_since(*since) >= threshold {
+            *alerted = true;
+            alerts::raise(
+                AlertKind::Waiting,
+                format!(
+                    "Someone has been waiting at the door for {}s",
+                    threshold.as_secs()
+                ),
+            );
+        }
+    }
+
+    fn leave(&mut self) {
+        self.last_motion = None;
+        self.waiting_since = None;
+        publish(PresenceKind::Left);
+    }
+
+    /// Forgets the previous frame, for when the camera goes away
+    ///
+    /// Whoever was present is considered gone, since they can no longer be seen.
+    pub fn reset(&mut self) {
+        self.previous = None;
+        if self.last_motion.is_some() {
+            self.leave();
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! Low frame rate preview streams for organizers answering the phone bell
+//!
+//! Streams always end on their own, and the door screen shows that the camera
+//! is active for as long as one is running.
+
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use tokio::time::{self, Instant, Interval, MissedTickBehavior};
+use tracing::info;
+
+use crate::audit::{self, AuditEvent};
+use crate::camera::encoding::{self, Format};
+use crate::camera::{latest_frame, privacy_mode};
+
+type Result<T> = std::result::Result<T, Box<dyn Error + Send + Sync>>;
+
+pub const DEFAULT_FPS: u32 = 2;
+pub const MAX_FPS: u32 = 5;
+pub const DEFAULT_DURATION: Duration = Duration::from_secs(60);
+pub const MAX_DURATION: Duration = Duration::from_secs(10 * 60);
+
+/// Longest side of preview frames
+const PREVIEW_DIMENSION: u32 = 480;
+const PREVIEW_QUALITY: u8 = 60;
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Whether a preview stream is running, for the camera indicator on screen
+#[must_use]
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// A running preview stream, which stops when dropped
+pub struct Preview {
+    interval: Interval,
+    until: Instant,
+    seq: u64,
+    /// Recorded in the audit log when the stream stops
+    stopped_by: String,
+}
+
+impl Preview {
+    /// Starts a stream at `fps` for `duration`, both clamped to their maximums
+    ///
+    /// # Errors
+    ///
+    /// Will error if the camera is disabled by privacy mode
+    pub fn start(fps: Option<u32>, duration: Option<Duration>, source: &str) -> Result<Self> {
+        if privacy_mode() {
+            return Err("camera is disabled by CAMERA_PRIVACY_MODE".into());
+        }
+        let fps = fps.unwrap_or(DEFAULT_FPS).clamp(1, MAX_FPS);
+        let duration = duration.unwrap_or(DEFAULT_DURATION).min(MAX_DURATION);
+
+        let mut interval = time::interval(Duration::from_secs(1) / fps);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        ACTIVE.store(true, Ordering::Relaxed);
+        info!(
+            fps,
+            duration_secs = duration.as_secs(),
+            source,
+            "camera preview started"
+        );
+        audit::record(&AuditEvent::CameraPreview {
+            active: true,
+            source: source.to_string(),
+        });
+        Ok(Self {
+            interval,
+            until: Instant::now() + duration,
+            seq: 0,
+            stopped_by: source.to_string(),
+        })
+    }
+
+    /// Waits until the next frame is due, returning its sequence number
+    ///
+    /// Returns `None` once the stream has run for its duration.
+    pub async fn tick(&mut self) -> Option<u64> {
+        let tick = self.interval.tick().await;
+        if tick >= self.until {
+            self.stopped_by = String::from("timeout");
+            return None;
+        }
+        self.seq += 1;
+        Some(self.seq)
+    }
+}
+
+impl Drop for Preview {
+    fn drop(&mut self) {
+        ACTIVE.store(false, Ordering::Relaxed);
+        info!(source = self.stopped_by, "camera preview stopped");
+        audit::record(&AuditEvent::CameraPreview {
+            active: false,
+            source: std::mem::take(&mut self.stopped_by),
+        });
+    }
+}
+
+/// Encodes the latest frame as a small JPEG for a preview stream
+///
+/// # Errors
+///
+/// Will error if the camera has no frame or it cannot be encoded
+pub fn frame() -> Result<Vec<u8>> {
+    let frame = latest_frame().ok_or("camera has no frame")?;
+    let config = encoding::Config {
+        format: Format::Jpeg,
+        quality: PREVIEW_QUALITY,
+        max_dimension: PREVIEW_DIMENSION,
+        max_bytes: None,
+    };
+    Ok(encoding::encode(&frame.image, &config)?.bytes)
+}
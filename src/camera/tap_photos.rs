@@ -0,0 +1,222 @@
+//! Photos of whoever tapped a tag, taken as the tap is read
+//!
+//! `TAP_PHOTOS` chooses which taps are photographed: `off` (the default),
+//! `failures` or `all`. Photos are saved to `TAP_PHOTO_DIR` and named in the
+//! tap's audit log entry, and old photos are deleted as new ones are saved.
+//! With `TAP_PHOTO_UPLOAD` set, they are also sent to the server.
+
+use std::sync::LazyLock;
+#[cfg(feature = "nfc_reader")]
+use std::{
+    cmp::Reverse,
+    env, fs,
+    path::PathBuf,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+#[cfg(feature = "nfc_reader")]
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+#[cfg(feature = "nfc_reader")]
+use tracing::{error, info, warn};
+
+#[cfg(feature = "nfc_reader")]
+use crate::camera::{self, Photo, encoding::Format};
+
+/// Photo location used when `TAP_PHOTO_DIR` is unset
+#[cfg(feature = "nfc_reader")]
+pub const DEFAULT_TAP_PHOTO_DIR: &str = "tap_photos";
+/// Photos kept when `TAP_PHOTO_MAX_COUNT` is unset
+#[cfg(feature = "nfc_reader")]
+pub const DEFAULT_MAX_COUNT: usize = 500;
+/// How long photos are kept when `TAP_PHOTO_MAX_AGE_SECS` is unset
+#[cfg(feature = "nfc_reader")]
+pub const DEFAULT_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Start of every tap photo's file name, so other files in the directory are left alone
+#[cfg(feature = "nfc_reader")]
+const NAME_PREFIX: &str = "tap-";
+
+/// Photos kept for a slow websocket before the oldest are dropped
+const UPLOAD_BUFFER: usize = 4;
+
+/// Which taps are photographed
+#[cfg(feature = "nfc_reader")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    Off,
+    /// Only taps which did not open the door
+    Failures,
+    All,
+}
+
+#[cfg(feature = "nfc_reader")]
+impl Policy {
+    /// Reads `TAP_PHOTOS`, which defaults to `off`
+    #[must_use]
+    pub fn from_env() -> Self {
+        if camera::privacy_mode() {
+            return Self::Off;
+        }
+        match env::var("TAP_PHOTOS").as_deref() {
+            Ok("all") => Self::All,
+            Ok("failures") => Self::Failures,
+            Err(_) | Ok("off") => Self::Off,
+            Ok(other) => {
+                error!(value = other, "unknown TAP_PHOTOS, not taking photos");
+                Self::Off
+            }
+        }
+    }
+}
+
+/// A tap photo sent to the server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TapPhoto {
+    /// When the tap was read, as a Unix timestamp in milliseconds
+    pub timestamp: i64,
+    pub passport_id: Option<i32>,
+    /// Scan result, as recorded in the audit log
+    pub result: String,
+    /// Base64 data URL
+    pub data: String,
+}
+
+static UPLOADS: LazyLock<broadcast::Sender<TapPhoto>> =
+    LazyLock::new(|| broadcast::channel(UPLOAD_BUFFER).0);
+
+/// Receives tap photos to upload, taken from now on
+#[must_use]
+pub fn subscribe() -> broadcast::Receiver<TapPhoto> {
+    UPLOADS.subscribe()
+}
+
+#[cfg(feature = "nfc_reader")]
+fn photo_dir() -> PathBuf {
+    env::var("TAP_PHOTO_DIR")
+        .unwrap_or_else(|_| DEFAULT_TAP_PHOTO_DIR.into())
+        .into()
+}
+
+/// A photo being taken while a tap is checked
+#[cfg(feature = "nfc_reader")]
+pub struct PendingPhoto {
+    policy: Policy,
+    timestamp: i64,
+    capture: JoinHandle<Option<Photo>>,
+}
+
+/// Starts taking a photo for a tap, unless `TAP_PHOTOS` is off
+#[cfg(feature = "nfc_reader")]
+#[must_use]
+pub fn start_capture() -> Option<PendingPhoto> {
+    let policy = Policy::from_env();
+    if policy == Policy::Off {
+        return None;
+    }
+    let capture = thread::spawn(|| {
+        camera::capture()
+            .inspect_err(|e| warn!(error = %e, "failed to take tap photo"))
+            .ok()
+    });
+    Some(PendingPhoto {
+        policy,
+        timestamp: Utc::now().timestamp_millis(),
+        capture,
+    })
+}
+
+#[cfg(feature = "nfc_reader")]
+impl PendingPhoto {
+    /// Keeps the photo if the policy wants it, returning its file name
+    ///
+    /// Waits for the photo to be taken. `opened` is whether the tap opened the
+    /// door, and `result` is its scan result.
+    pub fn finish(self, opened: bool, passport_id: Option<i32>, result: &str) -> Option<String> {
+        let photo = self.capture.join().ok().flatten()?;
+        if opened && self.policy == Policy::Failures {
+            return None;
+        }
+
+        let name = format!(
+            "{NAME_PREFIX}{}-{result}.{}",
+            self.timestamp,
+            photo.format.extension()
+        );
+        let dir = photo_dir();
+        if let Err(e) =
+            fs::create_dir_all(&dir).and_then(|()| fs::write(dir.join(&name), &photo.bytes))
+        {
+            error!(error = %e, "failed to save tap photo");
+            return None;
+        }
+        prune();
+
+        if env::var("TAP_PHOTO_UPLOAD").is_ok_and(|value| value == "true" || value == "1") {
+            // Failing to send only means no websocket is connected to upload it
+            let _ = UPLOADS.send(TapPhoto {
+                timestamp: self.timestamp,
+                passport_id,
+                result: result.to_string(),
+                data: photo.data_url(),
+            });
+        }
+        Some(name)
+    }
+}
+
+/// Whether a file name is one `PendingPhoto::finish` saves photos under
+#[cfg(feature = "nfc_reader")]
+fn is_tap_photo(name: &str) -> bool {
+    name.strip_prefix(NAME_PREFIX)
+        .and_then(|name| name.rsplit_once('.'))
+        .is_some_and(|(_, extension)| {
+            Format::ALL
+                .iter()
+                .any(|format| format.extension() == extension)
+        })
+}
+
+/// Deletes photos past `TAP_PHOTO_MAX_AGE_SECS` or beyond `TAP_PHOTO_MAX_COUNT`
+#[cfg(feature = "nfc_reader")]
+fn prune() {
+    let max_count = env::var("TAP_PHOTO_MAX_COUNT")
+        .ok()
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(DEFAULT_MAX_COUNT);
+    let max_age = env::var("TAP_PHOTO_MAX_AGE_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .map_or(DEFAULT_MAX_AGE, Duration::from_secs);
+
+    let dir = photo_dir();
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!(error = %e, "failed to list tap photos");
+            return;
+        }
+    };
+    let mut photos: Vec<_> = entries
+        .filter_map(Result::ok)
+        .filter(|entry| is_tap_photo(&entry.file_name().to_string_lossy()))
+        .filter_map(|entry| {
+            let modified = entry.metadata().and_then(|m| m.modified()).ok()?;
+            Some((modified, entry.path()))
+        })
+        .collect();
+    // Newest first, so everything past `max_count` is the oldest
+    photos.sort_by_key(|(modified, _)| Reverse(*modified));
+
+    for (i, (modified, path)) in photos.iter().enumerate() {
+        let expired = modified.elapsed().is_ok_and(|age| age > max_age);
+        if i >= max_count || expired {
+            match fs::remove_file(path) {
+                Ok(()) => info!(path = %path.display(), "deleted old tap photo"),
+                Err(e) => error!(path = %path.display(), error = %e, "failed to delete tap photo"),
+            }
+        }
+    }
+}
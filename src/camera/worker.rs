@@ -0,0 +1,405 @@
+//! Long-lived camera worker, keeping the latest frame ready to use
+//!
+//! The worker holds the camera open and reads frames continuously, so photos
+//! are taken instantly instead of waiting seconds for the device to open. If
+//! the camera fails, for example because it was unplugged, it is reopened
+//! until it comes back.
+//!
+//! The camera is configured with:
+//!
+//! - `CAMERA_BACKEND`: `device` (the default), or `test_pattern` for a
+//!   synthetic image when no camera is attached
+//! - `CAMERA_DEVICE`: device index, or part of its name (default `0`)
+//! - `CAMERA_RESOLUTION`: e.g. `1280x720` (the default)
+//! - `CAMERA_FORMAT`: `mjpeg` (the default), `yuyv`, `nv12`, `gray` or `rawrgb`
+//! - `CAMERA_FPS`: frames read per second (default 5)
+//! - `CAMERA_ROTATION`: `0` (the default), `90`, `180` or `270` degrees clockwise
+
+use std::env;
+use std::error::Error;
+use std::sync::{Arc, LazyLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use image::{Rgb, RgbImage, imageops};
+use nokhwa::{
+    Camera,
+    pixel_format::RgbFormat,
+    utils::{
+        ApiBackend, CameraFormat, CameraIndex, FrameFormat, RequestedFormat, RequestedFormatType,
+        Resolution,
+    },
+};
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+
+use crate::camera::motion::Detector;
+use crate::camera::privacy_mode;
+use crate::health::{self, Status, Subsystem};
+use crate::shutdown;
+
+type Result<T> = std::result::Result<T, Box<dyn Error + Send + Sync>>;
+
+const REOPEN_DELAY: Duration = Duration::from_secs(5);
+
+/// A frame read from the camera, already rotated
+pub struct Frame {
+    pub image: RgbImage,
+    pub captured_at: Instant,
+}
+
+static LATEST: LazyLock<watch::Sender<Option<Arc<Frame>>>> =
+    LazyLock::new(|| watch::Sender::new(None));
+
+/// The most recent frame, or `None` while the camera is unavailable
+#[must_use]
+pub fn latest_frame() -> Option<Arc<Frame>> {
+    LATEST.borrow().clone()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Backend {
+    Device,
+    TestPattern,
+}
+
+#[derive(Debug, Clone)]
+enum Device {
+    Index(u32),
+    /// Part of the device's name
+    Name(String),
+}
+
+#[derive(Debug, Clone)]
+struct Config {
+    backend: Backend,
+    device: Device,
+    width: u32,
+    height: u32,
+    format: FrameFormat,
+    fps: u32,
+    /// Degrees clockwise, a multiple of 90
+    rotation: u16,
+}
+
+impl Config {
+    /// Reads the camera configuration, warning about and ignoring invalid values
+    fn from_env() -> Self {
+        let backend = match env::var("CAMERA_BACKEND").as_deref() {
+            Err(_) | Ok("device") => Backend::Device,
+            Ok("test_pattern") => Backend::TestPattern,
+            Ok(other) => {
+                warn!(value = other, "unknown CAMERA_BACKEND, using the device");
+                Backend::Device
+            }
+        };
+        let device = env::var("CAMERA_DEVICE").map_or(Device::Index(0), |device| {
+            device
+                .parse()
+                .map_or_else(|_| Device::Name(device), Device::Index)
+        });
+        let (width, height) = env::var("CAMERA_RESOLUTION")
+            .ok()
+            .and_then(|resolution| {
+                let parsed = resolution.split_once('x').and_then(|(width, height)| {
+                    Some((width.trim().parse().ok()?, height.trim().parse().ok()?))
+                });
+                if parsed.is_none() {
+                    warn!(
+                        value = resolution,
+                        "invalid CAMERA_RESOLUTION, using 1280x720"
+                    );
+                }
+                parsed
+            })
+            .unwrap_or((1280, 720));
+        let format = match env::var("CAMERA_FORMAT").as_deref() {
+            Err(_) | Ok("mjpeg") => FrameFormat::MJPEG,
+            Ok("yuyv") => FrameFormat::YUYV,
+            Ok("nv12") => FrameFormat::NV12,
+            Ok("gray") => FrameFormat::GRAY,
+            Ok("rawrgb") => FrameFormat::RAWRGB,
+            Ok(other) => {
+                warn!(value = other, "unknown CAMERA_FORMAT, using mjpeg");
+                FrameFormat::MJPEG
+            }
+        };
+        let fps = env::var("CAMERA_FPS")
+            .ok()
+            .and_then(|fps| fps.parse().ok())
+            .filter(|fps| *fps > 0)
+            .unwrap_or(5);
+        let rotation = match env::var("CAMERA_ROTATION").as_deref() {
+            Err(_) | Ok("0") => 0,
+            Ok("90") => 90,
+            Ok("180") => 180,
+            Ok("270") => 270,
+            Ok(other) => {
+                warn!(value = other, "invalid CAMERA_ROTATION, not rotating");
+                0
+            }
+        };
+
+        Self {
+            backend,
+            device,
+            width,
+            height,
+            format,
+            fps,
+            rotation,
+        }
+    }
+
+    fn frame_interval(&self) -> Duration {
+        Duration::from_secs(1) / self.fps
+    }
+}
+
+/// Where frames come from
+trait FrameSource {
+    /// Waits for and returns the next frame
+    fn frame(&mut self) -> Result<RgbImage>;
+}
+
+struct DeviceSource {
+    camera: Camera,
+}
+
+impl DeviceSource {
+    fn open(config: &Config) -> Result<Self> {
+        let index = match &config.device {
+            Device::Index(index) => CameraIndex::Index(*index),
+            Device::Name(name) => nokhwa::query(ApiBackend::Auto)?
+                .into_iter()
+                .find(|info| info.human_name().contains(name.as_str()))
+                .map(|info| info.index().clone())
+                .ok_or_else(|| format!("no camera named {name:?}"))?,
+        };
+        let requested =
+            RequestedFormat::new::<RgbFormat>(RequestedFormatType::Closest(CameraFormat::new(
+                Resolution::new(config.width, config.height),
+                config.format,
+                config.fps,
+            )));
+        let mut camera = Camera::new(index, requested)?;
+        camera.open_stream()?;
+        info!(
+            camera = camera.info().human_name(),
+            format = ?camera.camera_format(),
+            "camera opened"
+        );
+        Ok(Self { camera })
+    }
+}
+
+impl FrameSource for DeviceSource {
+    fn frame(&mut self) -> Result<RgbImage> {
+        Ok(self.camera.frame()?.decode_image::<RgbFormat>()?)
+    }
+}
+
+impl Drop for DeviceSource {
+    fn drop(&mut self) {
+        let _ = self.camera.stop_stream();
+    }
+}
+
+/// Synthetic colour bars with a moving stripe, for running without a camera
+struct TestPattern {
+    width: u32,
+    height: u32,
+    frames: u32,
+}
+
+impl FrameSource for TestPattern {
+    fn frame(&mut self) -> Result<RgbImage> {
+        const BARS: [[u8; 3]; 7] = [
+            [255, 255, 255],
+            [255, 255, 0],
+            [0, 255, 255],
+            [0, 255, 0],
+            [255, 0, 255],
+            [255, 0, 0],
+            [0, 0, 255],
+        ];
+
+        self.frames = self.frames.wrapping_add(1);
+        let stripe = self.frames.wrapping_mul(8) % self.width.max(1);
+        Ok(RgbImage::from_fn(self.width, self.height, |x, _| {
+            if x.abs_diff(stripe) < 4 {
+                Rgb([0, 0, 0])
+            } else {
+                let bar = (x * 7 / self.width.max(1)) as usize;
+                Rgb(BARS[bar.min(BARS.len() - 1)])
+            }
+        }))
+    }
+}
+
+fn open(config: &Config) -> Result<Box<dyn FrameSource>> {
+    Ok(match config.backend {
+        Backend::Device => Box::new(DeviceSource::open(config)?),
+        Backend::TestPattern => Box::new(TestPattern {
+            width: config.width,
+            height: config.height,
+            frames: 0,
+        }),
+    })
+}
+
+fn rotate(image: RgbImage, rotation: u16) -> RgbImage {
+    match rotation {
+        90 => imageops::rotate90(&image),
+        180 => imageops::rotate180(&image),
+        270 => imageops::rotate270(&image),
+        _ => image,
+    }
+}
+
+/// Camera worker thread, keeping the latest frame until shutdown
+///
+/// Does nothing if `CAMERA_PRIVACY_MODE` is on.
+pub fn camera_entry() {
+    if privacy_mode() {
+        info!("camera privacy mode is on, camera disabled");
+        health::set_status(Subsystem::Camera, Status::Disabled);
+        return;
+    }
+    let config = Config::from_env();
+    let mut detector = Detector::from_env();
+
+    while !shutdown::is_triggered() {
+        health::beat(Subsystem::Camera);
+        let mut source = match open(&config) {
+            Ok(source) => source,
+            Err(e) => {
+                error!(error = %e, retry_in_secs = REOPEN_DELAY.as_secs(), "failed to open camera");
+                health::set_status(
+                    Subsystem::Camera,
+                    Status::Degraded(String::from("camera unavailable")),
+                );
+                thread::sleep(REOPEN_DELAY);
+                continue;
+            }
+        };
+        health::set_status(Subsystem::Camera, Status::Ready);
+
+        let mut next_frame = Instant::now();
+        while !shutdown::is_triggered() {
+            health::beat(Subsystem::Camera);
+            match source.frame() {
+                Ok(image) => {
+                    let image = rotate(image, config.rotation);
+                    let captured_at = Instant::now();
+                    detector.observe(&image, captured_at);
+                    LATEST.send_replace(Some(Arc::new(Frame { image, captured_at })));
+                }
+                Err(e) => {
+                    // Most likely unplugged, so drop the device and open it again
+                    error!(error = %e, "failed to read camera frame, reopening camera");
+                    LATEST.send_replace(None);
+                    detector.reset();
+                    health::set_status(
+                        Subsystem::Camera,
+                        Status::Degraded(String::from("camera unavailable")),
+                    );
+                    break;
+                }
+            }
+
+            // Cameras that ignore the requested frame rate, and the test pattern, are read no faster than it
+            next_frame += config.frame_interval();
+            let now = Instant::now();
+            if next_frame > now {
+                thread::sleep(next_frame - now);
+            } else {
+                next_frame = now;
+            }
+        }
+    }
+
+    LATEST.send_replace(None);
+    detector.reset();
+    info!("camera closed");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::{self, MAX_FRAME_AGE, motion};
+
+    fn test_pattern(width: u32, height: u32) -> TestPattern {
+        TestPattern {
+            width,
+            height,
+            frames: 0,
+        }
+    }
+
+    #[test]
+    fn rotates_clockwise() {
+        let image = test_pattern(64, 48).frame().unwrap();
+        let (width, height) = image.dimensions();
+
+        let rotated = rotate(image.clone(), 90);
+        assert_eq!(rotated.dimensions(), (height, width));
+        // The top left corner ends up top right
+        assert_eq!(rotated.get_pixel(height - 1, 0), image.get_pixel(0, 0));
+
+        let rotated = rotate(image.clone(), 180);
+        assert_eq!(rotated.dimensions(), (width, height));
+        assert_eq!(
+            rotated.get_pixel(0, 0),
+            image.get_pixel(width - 1, height - 1)
+        );
+
+        let rotated = rotate(image.clone(), 270);
+        assert_eq!(rotated.dimensions(), (height, width));
+        assert_eq!(rotated.get_pixel(0, width - 1), image.get_pixel(0, 0));
+
+        assert_eq!(rotate(image.clone(), 0), image);
+    }
+
+    #[test]
+    fn capture_skips_stale_frames() {
+        let image = test_pattern(64, 48).frame().unwrap();
+
+        LATEST.send_replace(Some(Arc::new(Frame {
+            image: image.clone(),
+            captured_at: Instant::now().checked_sub(MAX_FRAME_AGE).unwrap(),
+        })));
+        assert!(camera::capture().is_err());
+
+        LATEST.send_replace(Some(Arc::new(Frame {
+            image,
+            captured_at: Instant::now(),
+        })));
+        assert!(camera::capture().is_ok());
+
+        LATEST.send_replace(None);
+    }
+
+    #[test]
+    fn moving_stripe_is_seen_as_motion() {
+        let mut pattern = test_pattern(1280, 720);
+        let mut detector = Detector::from_env();
+        let start = Instant::now();
+
+        let first = pattern.frame().unwrap();
+        detector.observe(&first, start);
+        detector.observe(&first, start);
+        assert!(!motion::is_present());
+
+        // Skipping ahead moves the stripe into other cells of the grid
+        for _ in 0..9 {
+            pattern.frame().unwrap();
+        }
+        let moved = pattern.frame().unwrap();
+        detector.observe(&moved, start);
+        assert!(motion::is_present());
+
+        detector.observe(&moved, start + motion::DEFAULT_PRESENCE_TIMEOUT);
+        assert!(!motion::is_present());
+    }
+}
@@ -1,11 +1,23 @@
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
 pub enum AuthState {
     #[default]
-    Idle = 0,
-    Pending = 1,
-    Valid = 2,
-    Invalid = 3,
-    NetError = 4,
-    NFCError = 5,
-    DoorHWNotReady = 6,
+    Idle,
+    Pending,
+    Valid,
+    Invalid,
+    NetError,
+    NFCError,
+    DoorHWNotReady,
+    /// Valid passport, but the access policy does not allow entry right now
+    OutsideHours {
+        /// Next opening as a Unix timestamp, if one is scheduled
+        next_open: Option<i64>,
+    },
+    /// The door is locked down and will not open for anyone
+    Lockdown,
+    /// Too many scans, so the passport was not checked
+    RateLimited {
+        /// When scanning may be tried again, as a Unix timestamp
+        retry_at: i64,
+    },
 }
@@ -0,0 +1,115 @@
+//! Event bus for what happens at the door
+//!
+//! Modules publish scans, auth states, open requests, door actuations and
+//! connectivity changes here, and anything interested subscribes, instead of
+//! being wired to each other with channels. Every event is stamped with when
+//! it was published.
+//!
+//! Open requests are the exception: the door opener receives them over their
+//! own lossless channel, and the bus only carries them for observers.
+
+use std::sync::{LazyLock, Mutex, PoisonError};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+
+use crate::enums::AuthState;
+use crate::hardware::door::OpenRequest;
+
+/// Events kept for a slow subscriber before the oldest are dropped
+const EVENT_BUFFER: usize = 64;
+
+/// A link to something outside the door opener
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Link {
+    /// The API websocket
+    Websocket,
+    /// The door module, such as the ada-pusher over BLE
+    DoorModule,
+}
+
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// A tag was read and checked, with its scan result as recorded in the audit log
+    Scan {
+        passport_id: Option<i32>,
+        result: String,
+    },
+    /// What the screen should show, also followed by audio
+    Auth(AuthState),
+    /// Something asked for the door to be opened, not yet checked against its source's policy
+    ///
+    /// Only for observers, requests are made with [`request_open`].
+    OpenRequested(OpenRequest),
+    /// The door module finished an actuation, including any retries
+    DoorActuated {
+        success: bool,
+    },
+    Connectivity {
+        link: Link,
+        connected: bool,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Envelope {
+    pub event: Event,
+    /// When the event was published, as a Unix timestamp in milliseconds
+    pub timestamp: i64,
+}
+
+static BUS: LazyLock<broadcast::Sender<Envelope>> =
+    LazyLock::new(|| broadcast::channel(EVENT_BUFFER).0);
+
+type OpenRequests = (
+    UnboundedSender<OpenRequest>,
+    Mutex<Option<UnboundedReceiver<OpenRequest>>>,
+);
+
+static OPEN_REQUESTS: LazyLock<OpenRequests> = LazyLock::new(|| {
+    let (tx, rx) = unbounded_channel();
+    (tx, Mutex::new(Some(rx)))
+});
+
+/// Publishes an event to every subscriber
+pub fn publish(event: Event) {
+    // Failing to send only means nothing is subscribed yet
+    let _ = BUS.send(Envelope {
+        event,
+        timestamp: Utc::now().timestamp_millis(),
+    });
+}
+
+/// Receives events published from now on
+///
+/// Subscribers which fall behind by more than the buffer miss the oldest
+/// events, and are told how many with `RecvError::Lagged`.
+#[must_use]
+pub fn subscribe() -> broadcast::Receiver<Envelope> {
+    BUS.subscribe()
+}
+
+/// Asks for the door to be opened, then publishes the request for observers
+///
+/// Unlike events on the bus, requests are never dropped, however far behind
+/// the door opener is.
+pub fn request_open(request: OpenRequest) {
+    // Failing to send only means the door opener has stopped for shutdown
+    let _ = OPEN_REQUESTS.0.send(request.clone());
+    publish(Event::OpenRequested(request));
+}
+
+/// Takes the receiving end of [`request_open`], which only the door opener has
+///
+/// Requests made before this are kept until they are received.
+#[must_use]
+pub fn take_open_requests() -> Option<UnboundedReceiver<OpenRequest>> {
+    OPEN_REQUESTS
+        .1
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take()
+}
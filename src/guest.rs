@@ -0,0 +1,335 @@
+//! Temporary guest passes
+//!
+//! Organizers create passes over the websocket or admin API and hand their
+//! codes to guests. While any pass is active, the door shows a presence code,
+//! also as a QR code, which rotates every [`PRESENCE_PERIOD_SECS`]. A guest
+//! redeems their pass by sending its code along with the presence code, which
+//! proves they are standing at the door.
+//!
+//! Passes are saved to `GUEST_PASSES_PATH` so they survive restarts.
+
+use std::collections::VecDeque;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tokio::{task, time};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::audit::{self, AuditEvent};
+use crate::hardware::door::{OpenRequest, OpenSource};
+use crate::persist;
+use crate::shutdown;
+
+type Result<T> = std::result::Result<T, Box<dyn Error + Send + Sync>>;
+
+/// Guest pass location used when `GUEST_PASSES_PATH` is unset
+pub const DEFAULT_GUEST_PASSES_PATH: &str = "guest_passes.json";
+/// Longest guest pass allowed when `GUEST_PASS_MAX_SECS` is unset
+pub const DEFAULT_GUEST_PASS_MAX: Duration = Duration::from_secs(24 * 60 * 60);
+/// How often the presence code changes
+pub const PRESENCE_PERIOD_SECS: i64 = 30;
+
+const PASS_CODE_DIGITS: u32 = 8;
+const PRESENCE_CODE_DIGITS: u32 = 6;
+/// Failed redemptions allowed per minute before all redemptions are refused
+const MAX_FAILED_REDEMPTIONS: usize = 10;
+const FAILED_REDEMPTION_WINDOW: Duration = Duration::from_secs(60);
+/// How often expired and used up passes are dropped
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GuestPass {
+    pub id: String,
+    /// Who the pass is for, shown in the audit log
+    pub label: String,
+    /// Code the guest redeems the pass with
+    pub code: String,
+    /// When the pass stops working, as a Unix timestamp
+    pub expires_at: i64,
+    pub uses_left: u32,
+}
+
+struct Presence {
+    period: i64,
+    current: String,
+    previous: String,
+}
+
+static PASSES: LazyLock<Mutex<Vec<GuestPass>>> = LazyLock::new(|| Mutex::new(load()));
+
+static PRESENCE: LazyLock<Mutex<Presence>> = LazyLock::new(|| {
+    Mutex::new(Presence {
+        period: 0,
+        current: random_code(PRESENCE_CODE_DIGITS),
+        previous: random_code(PRESENCE_CODE_DIGITS),
+    })
+});
+
+/// Whether any pass can be redeemed, kept up to date so the GUI never touches the passes
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+static FAILED_REDEMPTIONS: LazyLock<Mutex<VecDeque<Instant>>> =
+    LazyLock::new(|| Mutex::new(VecDeque::new()));
+
+fn random_code(digits: u32) -> String {
+    let code = Uuid::new_v4().as_u128() % 10u128.pow(digits);
+    format!("{code:0width$}", width = digits as usize)
+}
+
+fn passes_path() -> PathBuf {
+    env::var("GUEST_PASSES_PATH")
+        .unwrap_or_else(|_| DEFAULT_GUEST_PASSES_PATH.into())
+        .into()
+}
+
+fn max_duration() -> Duration {
+    env::var("GUEST_PASS_MAX_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .map_or(DEFAULT_GUEST_PASS_MAX, Duration::from_secs)
+}
+
+fn load() -> Vec<GuestPass> {
+    let path = passes_path();
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Vec::new(),
+        Err(e) => {
+            error!(path = %path.display(), error = %e, "failed to read guest passes");
+            return Vec::new();
+        }
+    };
+    serde_json::from_str(&contents).unwrap_or_else(|e| {
+        error!(path = %path.display(), error = %e, "failed to parse guest passes");
+        Vec::new()
+    })
+}
+
+fn save(passes: &[GuestPass]) {
+    // Every change to the passes is saved, so this follows them
+    let now = Utc::now().timestamp();
+    ACTIVE.store(
+        passes
+            .iter()
+            .any(|pass| pass.expires_at > now && pass.uses_left > 0),
+        Ordering::Relaxed,
+    );
+    if let Err(e) = persist::write_json(&passes_path(), &passes) {
+        error!(error = %e, "failed to save guest passes");
+    }
+}
+
+/// Records something happening to `pass`, or a redemption matching no pass if `None`
+fn record(pass: Option<&GuestPass>, action: &str, source: &str) {
+    audit::record(&AuditEvent::GuestPass {
+        pass_id: pass.map(|pass| pass.id.clone()),
+        label: pass.map(|pass| pass.label.clone()),
+        action: action.to_string(),
+        source: source.to_string(),
+    });
+}
+
+/// Locks the passes, dropping any which have expired or been used up
+fn active_passes() -> MutexGuard<'static, Vec<GuestPass>> {
+    let mut passes = PASSES.lock().unwrap_or_else(PoisonError::into_inner);
+    let now = Utc::now().timestamp();
+    let before = passes.len();
+    passes.retain(|pass| {
+        let active = pass.expires_at > now && pass.uses_left > 0;
+        if !active {
+            let action = if pass.uses_left == 0 {
+                "used_up"
+            } else {
+                "expired"
+            };
+            info!(pass_id = pass.id, action, "guest pass removed");
+            record(Some(pass), action, "expired");
+        }
+        active
+    });
+    if passes.len() != before {
+        save(&passes);
+    }
+    passes
+}
+
+/// Creates a pass which works `max_uses` times until `duration` has passed
+///
+/// The duration is capped at `GUEST_PASS_MAX_SECS`. `source` is recorded in
+/// the audit log.
+pub fn create(label: String, duration: Duration, max_uses: u32, source: &str) -> GuestPass {
+    let max = max_duration();
+    if duration > max {
+        warn!(
+            requested_secs = duration.as_secs(),
+            max_secs = max.as_secs(),
+            "guest pass duration capped"
+        );
+    }
+    let pass = GuestPass {
+        id: Uuid::new_v4().simple().to_string(),
+        label,
+        code: random_code(PASS_CODE_DIGITS),
+        expires_at: Utc::now()
+            .timestamp()
+            .saturating_add(i64::try_from(duration.min(max).as_secs()).unwrap_or(i64::MAX)),
+        uses_left: max_uses.max(1),
+    };
+
+    let mut passes = active_passes();
+    passes.push(pass.clone());
+    save(&passes);
+    info!(
+        pass_id = pass.id,
+        label = pass.label,
+        source,
+        "guest pass created"
+    );
+    record(Some(&pass), "created", source);
+    pass
+}
+
+/// Revokes a pass before it expires, returning whether it was active
+pub fn revoke(id: &str, source: &str) -> bool {
+    let mut passes = active_passes();
+    let Some(index) = passes.iter().position(|pass| pass.id == id) else {
+        return false;
+    };
+    let pass = passes.remove(index);
+    save(&passes);
+    info!(pass_id = pass.id, source, "guest pass revoked");
+    record(Some(&pass), "revoked", source);
+    true
+}
+
+/// Passes which can still be redeemed
+#[must_use]
+pub fn list() -> Vec<GuestPass> {
+    active_passes().clone()
+}
+
+/// Whether any pass can still be redeemed, as of the last expiry check
+#[must_use]
+pub fn has_active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Spawns the task dropping passes once they expire
+pub fn spawn_expiry() {
+    task::spawn(async {
+        let mut interval = time::interval(EXPIRY_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    // Also picks up the passes loaded at startup
+                    let passes = active_passes();
+                    ACTIVE.store(!passes.is_empty(), Ordering::Relaxed);
+                }
+                () = shutdown::wait() => return,
+            }
+        }
+    });
+}
+
+/// The presence code currently shown at the door
+#[must_use]
+pub fn presence_code() -> String {
+    current_presence(Utc::now().timestamp()).current.clone()
+}
+
+/// Link to the guest pass page in `GUEST_PASS_URL` with `presence_code` filled in
+///
+/// `None` if `GUEST_PASS_URL` is unset, in which case the code is only shown as text.
+#[must_use]
+pub fn presence_url(presence_code: &str) -> Option<String> {
+    let url = env::var("GUEST_PASS_URL").ok()?;
+    let separator = if url.contains('?') { '&' } else { '?' };
+    Some(format!("{url}{separator}door={presence_code}"))
+}
+
+fn current_presence(now: i64) -> MutexGuard<'static, Presence> {
+    let mut presence = PRESENCE.lock().unwrap_or_else(PoisonError::into_inner);
+    let period = now.div_euclid(PRESENCE_PERIOD_SECS);
+    if period != presence.period {
+        // The previous code is still accepted, unless a whole period went by unseen
+        presence.previous = if period == presence.period + 1 {
+            std::mem::take(&mut presence.current)
+        } else {
+            random_code(PRESENCE_CODE_DIGITS)
+        };
+        presence.current = random_code(PRESENCE_CODE_DIGITS);
+        presence.period = period;
+    }
+    presence
+}
+
+fn is_present(presence_code: &str) -> bool {
+    let presence = current_presence(Utc::now().timestamp());
+    presence_code == presence.current || presence_code == presence.previous
+}
+
+/// Redeems a pass, using it up once, so the caller can open the door
+///
+/// Every attempt is recorded in the audit log.
+///
+/// # Errors
+///
+/// Will error if the door is locked down or guest passes may not open it, too
+/// many redemptions have failed recently, the presence code is not the one
+/// shown at the door, or no active pass has the code
+pub fn redeem(code: &str, presence_code: &str, source: &str) -> Result<GuestPass> {
+    // Checked before a use is taken, so a refused open does not cost the guest one
+    match OpenRequest::new(OpenSource::GuestPass, None, source).refusal() {
+        Some("lockdown") => return Err("the door is locked down".into()),
+        Some(_) => return Err("guest passes cannot open the door".into()),
+        None => {}
+    }
+
+    let mut failures = FAILED_REDEMPTIONS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    while failures
+        .front()
+        .is_some_and(|at| at.elapsed() >= FAILED_REDEMPTION_WINDOW)
+    {
+        failures.pop_front();
+    }
+    if failures.len() >= MAX_FAILED_REDEMPTIONS {
+        return Err("too many failed attempts, try again in a minute".into());
+    }
+
+    if !is_present(presence_code) {
+        failures.push_back(Instant::now());
+        record(None, "rejected_presence_code", source);
+        return Err("presence code is wrong or has expired".into());
+    }
+
+    let mut passes = active_passes();
+    let Some(pass) = passes.iter_mut().find(|pass| pass.code == code) else {
+        failures.push_back(Instant::now());
+        record(None, "rejected_code", source);
+        return Err("no active guest pass has this code".into());
+    };
+
+    pass.uses_left -= 1;
+    let pass = pass.clone();
+    // Used up passes are dropped the next time the passes are looked at
+    save(&passes);
+    info!(
+        pass_id = pass.id,
+        uses_left = pass.uses_left,
+        source,
+        "guest pass redeemed"
+    );
+    record(Some(&pass), "redeemed", source);
+    Ok(pass)
+}
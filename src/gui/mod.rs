@@ -2,20 +2,43 @@ pub mod background;
 pub mod colors;
 pub mod font_engine;
 pub mod passport;
+pub mod screensaver;
 pub mod svg;
 
 mod constants;
+mod qr;
 mod windows;
 
+use std::env;
+
 use macroquad::prelude::*;
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::sync::broadcast::{self, error::TryRecvError};
 
 use self::constants::{OPACITY_MAX, OPACITY_MIN};
+use self::qr::QrImage;
+use self::screensaver::Screensaver;
 use self::{passport::PassportData, passport::draw_passport};
 
-use crate::gui::windows::draw_message_windows;
+use crate::camera::{motion, preview};
+#[cfg(debug_assertions)]
+use crate::events;
+use crate::events::{Envelope, Event};
+use crate::guest;
+use crate::gui::windows::{
+    draw_camera_indicator, draw_guest_pass_panel, draw_link_qr, draw_low_battery,
+    draw_message_windows, draw_mode_banner, draw_subsystem_warnings,
+};
+use crate::hardware::door::diagnostics;
+#[cfg(debug_assertions)]
+use crate::hardware::door::{OpenRequest, OpenSource};
+use crate::health::{self, Status, Subsystem};
+use crate::mode;
+use crate::shutdown;
 use crate::{enums::AuthState, timedvariable::TimedVariable};
-use AuthState::{DoorHWNotReady, Idle, Invalid, NFCError, NetError, Pending, Valid};
+use AuthState::{
+    DoorHWNotReady, Idle, Invalid, Lockdown, NFCError, NetError, OutsideHours, Pending,
+    RateLimited, Valid,
+};
 
 #[derive(Copy, Clone, Debug)]
 struct AnimationEvent {
@@ -63,7 +86,7 @@ pub fn colour_lerp(source: Color, destination: Color, percent: f32) -> Color {
 }
 
 #[allow(clippy::cast_possible_truncation)]
-pub fn gui_entry(nfc_messages: UnboundedReceiver<AuthState>, opener_tx: UnboundedSender<()>) {
+pub fn gui_entry(events: broadcast::Receiver<Envelope>) {
     macroquad::Window::from_config(
         Conf {
             window_title: "Door Opener".to_owned(),
@@ -72,11 +95,11 @@ pub fn gui_entry(nfc_messages: UnboundedReceiver<AuthState>, opener_tx: Unbounde
             sample_count: 0,
             ..Default::default()
         },
-        gui_main(nfc_messages, opener_tx),
+        gui_main(events),
     );
 }
 
-async fn gui_main(mut nfc_messages: UnboundedReceiver<AuthState>, opener_tx: UnboundedSender<()>) {
+async fn gui_main(mut events: broadcast::Receiver<Envelope>) {
     let mut queued_auth_state = AnimationEvent::new();
     let mut animating_auth_state: TimedVariable<AnimationEvent> =
         TimedVariable::new(AnimationEvent::new());
@@ -85,13 +108,21 @@ async fn gui_main(mut nfc_messages: UnboundedReceiver<AuthState>, opener_tx: Unb
     let mut active_message: TimedVariable<AuthState> = TimedVariable::new(AuthState::Idle);
 
     let mut opacities = MessageOpacities::default();
+    let mut message_details = MessageDetails::default();
 
     let segoe_ui = load_ttf_font_from_bytes(SEGOE_UI_FONT).unwrap();
 
     let background_data = background::initialise_background();
     let mut passport_data = passport::initialise_passport();
+    let mut guest_qr = QrImage::default();
+    let mut link_qrs = LinkQrs::from_env();
+    let mut screensaver = Screensaver::from_env();
+
+    health::set_status(Subsystem::Gui, Status::Ready);
 
     loop {
+        health::beat(Subsystem::Gui);
+
         let check_time = get_time();
         update_timed_variables(
             check_time,
@@ -107,7 +138,26 @@ async fn gui_main(mut nfc_messages: UnboundedReceiver<AuthState>, opener_tx: Unb
             &mut active_message,
         );
         advance_queued_animation(&mut animating_auth_state, &mut queued_auth_state);
-        receive_nfc_message(&mut nfc_messages, &mut queued_auth_state);
+        receive_auth_state(&mut events, &mut queued_auth_state);
+
+        // The camera indicator has to stay visible while a preview is running
+        screensaver.update(
+            queued_auth_state.state.is_some()
+                || auth_state.get() != AuthState::Idle
+                || active_message.get() != AuthState::Idle
+                || motion::is_present()
+                || preview::is_active(),
+        );
+
+        if screensaver.is_blank() {
+            clear_background(BLACK);
+            if exit_requested() {
+                return;
+            }
+            screensaver.throttle();
+            next_frame().await;
+            continue;
+        }
 
         let delta_time: f32 = get_frame_time();
         clear_background(Color::from_hex(0x000a_0a0a));
@@ -119,22 +169,41 @@ async fn gui_main(mut nfc_messages: UnboundedReceiver<AuthState>, opener_tx: Unb
             active_message.get(),
             delta_time,
         );
-        draw_message_windows(&opacities, &segoe_ui);
+        message_details.update(active_message.get());
+        draw_message_windows(&opacities, &message_details, &segoe_ui);
+        link_qrs.draw(&opacities, &message_details, &segoe_ui);
         draw_passport_for_state(auth_state.get(), &mut passport_data);
+        draw_mode_banner(mode::is_locked_down(), mode::open_house(), &segoe_ui);
+        draw_guest_pass(&mut guest_qr, &segoe_ui);
+        if preview::is_active() {
+            draw_camera_indicator(&segoe_ui);
+        }
+        if let Some(percent) = diagnostics::low_battery() {
+            draw_low_battery(percent, &segoe_ui);
+        }
+        draw_subsystem_warnings(&health::snapshot(), &segoe_ui);
+        screensaver.draw();
 
-        #[cfg(not(debug_assertions))]
-        let _ = &opener_tx;
         #[cfg(debug_assertions)]
-        handle_debug_open(&mut queued_auth_state, &opener_tx);
+        handle_debug_open(&mut queued_auth_state);
 
-        if is_key_down(KeyCode::Escape) {
+        if exit_requested() {
             return;
         }
 
+        screensaver.throttle();
         next_frame().await;
     }
 }
 
+/// Whether the GUI should close, triggering shutdown if Escape is pressed
+fn exit_requested() -> bool {
+    if is_key_down(KeyCode::Escape) {
+        shutdown::trigger();
+    }
+    shutdown::is_triggered()
+}
+
 struct MessageOpacities {
     welcome: f32,
     accepted: f32,
@@ -142,6 +211,108 @@ struct MessageOpacities {
     net_error: f32,
     nfc_error: f32,
     doorhw_not_ready_error: f32,
+    outside_hours: f32,
+    lockdown: f32,
+    rate_limited: f32,
+}
+
+impl MessageOpacities {
+    /// Opacity of whichever error message is showing
+    fn rejection(&self) -> f32 {
+        [
+            self.rejected,
+            self.net_error,
+            self.nfc_error,
+            self.doorhw_not_ready_error,
+            self.outside_hours,
+            self.lockdown,
+            self.rate_limited,
+        ]
+        .into_iter()
+        .fold(OPACITY_MIN, f32::max)
+    }
+}
+
+/// Details shown in messages, kept after the message starts fading out
+#[derive(Default)]
+struct MessageDetails {
+    next_open: Option<i64>,
+    retry_at: i64,
+    /// Why the last attempt was rejected, filled into `REJECTION_QR_URL`
+    rejection: Option<&'static str>,
+}
+
+impl MessageDetails {
+    fn update(&mut self, auth_state: AuthState) {
+        match auth_state {
+            OutsideHours { next_open } => self.next_open = next_open,
+            RateLimited { retry_at } => self.retry_at = retry_at,
+            _ => {}
+        }
+        if let Some(reason) = rejection_reason(auth_state) {
+            self.rejection = Some(reason);
+        }
+    }
+}
+
+fn rejection_reason(auth_state: AuthState) -> Option<&'static str> {
+    match auth_state {
+        Invalid => Some("invalid"),
+        NetError => Some("net_error"),
+        NFCError => Some("nfc_error"),
+        DoorHWNotReady => Some("door_not_ready"),
+        OutsideHours { .. } => Some("outside_hours"),
+        Lockdown => Some("lockdown"),
+        RateLimited { .. } => Some("rate_limited"),
+        Idle | Pending | Valid => None,
+    }
+}
+
+/// QR codes pointing visitors somewhere useful, shown below the messages
+///
+/// `WELCOME_QR_URL` is shown with the welcome message, e.g. for the phone bell
+/// number or passport sign-up. `REJECTION_QR_URL` is shown after a rejection,
+/// with `{reason}` replaced by why, so it can deep link to e.g. passport
+/// activation.
+struct LinkQrs {
+    welcome: Option<QrImage>,
+    rejection_url: Option<String>,
+    rejection: QrImage,
+}
+
+impl LinkQrs {
+    fn from_env() -> Self {
+        let welcome = env::var("WELCOME_QR_URL").ok().map(|url| {
+            let mut qr = QrImage::default();
+            qr.set(&url);
+            qr
+        });
+        Self {
+            welcome,
+            rejection_url: env::var("REJECTION_QR_URL").ok(),
+            rejection: QrImage::default(),
+        }
+    }
+
+    fn draw(&mut self, opacities: &MessageOpacities, details: &MessageDetails, font: &Font) {
+        if let Some(welcome) = &self.welcome {
+            draw_link_qr(
+                welcome,
+                "Scan to call the phone bell or get a passport",
+                opacities.welcome,
+                font,
+            );
+        }
+        if let (Some(url), Some(reason)) = (&self.rejection_url, details.rejection) {
+            self.rejection.set(&url.replace("{reason}", reason));
+            draw_link_qr(
+                &self.rejection,
+                "Scan for help",
+                opacities.rejection(),
+                font,
+            );
+        }
+    }
 }
 
 impl Default for MessageOpacities {
@@ -153,6 +324,9 @@ impl Default for MessageOpacities {
             net_error: 0.0,
             nfc_error: 0.0,
             doorhw_not_ready_error: 0.0,
+            outside_hours: 0.0,
+            lockdown: 0.0,
+            rate_limited: 0.0,
         }
     }
 }
@@ -209,7 +383,13 @@ fn process_animation_state(
                 active_message.set(AuthState::Idle, 6.5);
                 animating_auth_state.set(AnimationEvent::reset_trigger(), 2.0);
             }
-            Invalid | NetError | NFCError | DoorHWNotReady => {
+            Invalid
+            | NetError
+            | NFCError
+            | DoorHWNotReady
+            | OutsideHours { .. }
+            | Lockdown
+            | RateLimited { .. } => {
                 auth_state.set(anim_state, -1.0);
                 active_message.set(anim_state, -1.0);
 
@@ -251,17 +431,27 @@ fn advance_queued_animation(
     }
 }
 
-fn receive_nfc_message(
-    nfc_messages: &mut UnboundedReceiver<AuthState>,
+/// Queues the next auth state published, skipping other events
+fn receive_auth_state(
+    events: &mut broadcast::Receiver<Envelope>,
     queued_auth_state: &mut AnimationEvent,
 ) {
-    if let Ok(x) = nfc_messages.try_recv() {
-        *queued_auth_state = AnimationEvent {
-            state: Some(x),
-            triggered: true,
-        };
-    } else {
-        // probably display the error message somehow
+    loop {
+        match events.try_recv() {
+            Ok(Envelope {
+                event: Event::Auth(x),
+                ..
+            }) => {
+                *queued_auth_state = AnimationEvent {
+                    state: Some(x),
+                    triggered: true,
+                };
+                return;
+            }
+            // Missed states are skipped, the next one is shown instead
+            Ok(_) | Err(TryRecvError::Lagged(_)) => {}
+            Err(TryRecvError::Empty | TryRecvError::Closed) => return,
+        }
     }
 }
 
@@ -301,6 +491,34 @@ fn update_message_opacities(
         show && auth_state == AuthState::DoorHWNotReady,
         delta_time,
     );
+    update_opacity(
+        &mut opacities.outside_hours,
+        show && matches!(auth_state, AuthState::OutsideHours { .. }),
+        delta_time,
+    );
+    update_opacity(
+        &mut opacities.lockdown,
+        show && auth_state == AuthState::Lockdown,
+        delta_time,
+    );
+    update_opacity(
+        &mut opacities.rate_limited,
+        show && matches!(auth_state, AuthState::RateLimited { .. }),
+        delta_time,
+    );
+}
+
+/// Shows the rotating presence code while any guest pass can be redeemed
+fn draw_guest_pass(qr: &mut QrImage, font: &Font) {
+    if mode::is_locked_down() || !guest::has_active() {
+        return;
+    }
+    let presence_code = guest::presence_code();
+    let url = guest::presence_url(&presence_code);
+    if let Some(url) = &url {
+        qr.set(url);
+    }
+    draw_guest_pass_panel(url.is_some().then_some(&*qr), &presence_code, font);
 }
 
 fn draw_passport_for_state(auth_state: AuthState, passport_data: &mut PassportData) {
@@ -313,13 +531,13 @@ fn draw_passport_for_state(auth_state: AuthState, passport_data: &mut PassportDa
 }
 
 #[cfg(debug_assertions)]
-fn handle_debug_open(queued_auth_state: &mut AnimationEvent, opener_tx: &UnboundedSender<()>) {
+fn handle_debug_open(queued_auth_state: &mut AnimationEvent) {
     if is_key_pressed(KeyCode::Space) {
         println!("Opening door for debugging purposes...");
         *queued_auth_state = AnimationEvent {
             state: Some(Valid),
             triggered: true,
         };
-        let _ = opener_tx.send(());
+        events::request_open(OpenRequest::new(OpenSource::Debug, None, "space_key"));
     }
 }
@@ -150,7 +150,10 @@ fn update_spinner_state(state: AuthState, delta_time: f32, passport_data: &mut P
         AuthState::Invalid
         | AuthState::NetError
         | AuthState::NFCError
-        | AuthState::DoorHWNotReady => RED_CL,
+        | AuthState::DoorHWNotReady
+        | AuthState::OutsideHours { .. }
+        | AuthState::Lockdown
+        | AuthState::RateLimited { .. } => RED_CL,
     };
 
     passport_data.current_spinner_colour = super::colour_lerp(
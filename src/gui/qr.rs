@@ -0,0 +1,65 @@
+use macroquad::prelude::*;
+use qrcode::QrCode;
+use tracing::error;
+
+/// Light modules around the code, which scanners need to find its edges
+const QUIET_ZONE: usize = 4;
+
+/// A QR code ready to draw, only re-encoded when its contents change
+#[derive(Default)]
+pub struct QrImage {
+    contents: String,
+    width: usize,
+    dark: Vec<bool>,
+}
+
+impl QrImage {
+    /// Encodes `contents`, unless they are already encoded
+    pub fn set(&mut self, contents: &str) {
+        if self.contents == contents {
+            return;
+        }
+        self.contents = contents.to_string();
+        match QrCode::new(contents) {
+            Ok(code) => {
+                self.width = code.width();
+                self.dark = code
+                    .to_colors()
+                    .into_iter()
+                    .map(|colour| colour == qrcode::Color::Dark)
+                    .collect();
+            }
+            Err(e) => {
+                error!(error = %e, "failed to encode QR code");
+                self.width = 0;
+                self.dark.clear();
+            }
+        }
+    }
+
+    /// Draws the code in a square of `size` pixels at (`x`, `y`), quiet zone included
+    #[allow(clippy::cast_precision_loss)]
+    pub fn draw(&self, x: f32, y: f32, size: f32, opacity: u8) {
+        if self.width == 0 || opacity == 0 {
+            return;
+        }
+
+        // Whole pixel modules keep the code crisp enough to scan
+        let modules = self.width + QUIET_ZONE * 2;
+        let module_size = (size / modules as f32).floor().max(1.0);
+        let offset = (size - module_size * modules as f32) / 2.0;
+
+        draw_rectangle(x, y, size, size, Color::from_rgba(255, 255, 255, opacity));
+        for (i, _) in self.dark.iter().enumerate().filter(|(_, dark)| **dark) {
+            let column = (i % self.width + QUIET_ZONE) as f32;
+            let row = (i / self.width + QUIET_ZONE) as f32;
+            draw_rectangle(
+                x + offset + column * module_size,
+                y + offset + row * module_size,
+                module_size,
+                module_size,
+                Color::from_rgba(0, 0, 0, opacity),
+            );
+        }
+    }
+}
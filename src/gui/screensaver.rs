@@ -0,0 +1,214 @@
+//! Dims or blanks the screen when nobody has used the door for a while
+//!
+//! While asleep, frames are drawn at a low rate to save power. A tap, a
+//! websocket message or someone arriving at the door wakes the screen.
+//!
+//! The screensaver is configured with:
+//!
+//! - `SCREENSAVER_MODE`: `dim` (the default), `blank`, `power_off` to also turn
+//!   the display off through sway, or `off`
+//! - `SCREENSAVER_IDLE_SECS`: seconds of inactivity before sleeping (default 120)
+//! - `SCREENSAVER_FPS`: frames drawn per second while asleep (default 2)
+//! - `SCREENSAVER_HOURS`: when the screensaver may apply, either daily windows
+//!   such as `22:00-08:00,12:00-13:00`, or `closed` for whenever the access
+//!   policy has the door closed to members (default any time)
+
+use std::env;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use macroquad::prelude::*;
+use tracing::{info, warn};
+
+use crate::gui::colors::BLACK_BG;
+use crate::policy::{AccessPolicy, DailyHours};
+
+/// Inactivity before sleeping when `SCREENSAVER_IDLE_SECS` is unset
+pub const DEFAULT_IDLE: Duration = Duration::from_secs(120);
+/// Frame rate while asleep when `SCREENSAVER_FPS` is unset
+pub const DEFAULT_SLEEP_FPS: u32 = 2;
+
+/// Opacity of the overlay dimming the screen
+const DIM_OPACITY: u8 = 200;
+/// How often `SCREENSAVER_HOURS` is checked again, since `closed` reads the policy file
+const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+static WAKE: AtomicBool = AtomicBool::new(false);
+
+/// Wakes the screen, from any thread
+pub fn wake() {
+    WAKE.store(true, Ordering::Relaxed);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Off,
+    Dim,
+    Blank,
+    /// Blanks the screen and turns the display off
+    PowerOff,
+}
+
+#[derive(Debug, Clone)]
+enum Schedule {
+    Always,
+    /// Whenever the access policy has the door closed to members
+    WhileClosed,
+    Daily(DailyHours),
+}
+
+impl Schedule {
+    fn parse(value: &str) -> Option<Self> {
+        if value == "closed" {
+            return Some(Self::WhileClosed);
+        }
+        DailyHours::parse(value).map(Self::Daily)
+    }
+
+    fn applies_now(&self) -> bool {
+        match self {
+            Self::Always => true,
+            Self::WhileClosed => match AccessPolicy::from_env() {
+                Ok(Some(policy)) => !policy.is_open_now(),
+                // Without a policy the door is never closed
+                Ok(None) => false,
+                Err(e) => {
+                    warn!(error = %e, "failed to load access policy, keeping the screen on");
+                    false
+                }
+            },
+            Self::Daily(hours) => hours.contains_now(),
+        }
+    }
+}
+
+pub struct Screensaver {
+    mode: Mode,
+    idle_after: Duration,
+    sleep_frame: Duration,
+    schedule: Schedule,
+    last_activity: Instant,
+    /// Whether the schedule applied when last checked, and when to check it again
+    scheduled: Option<(bool, Instant)>,
+    asleep: bool,
+}
+
+impl Screensaver {
+    /// Reads the screensaver configuration, warning about and ignoring invalid values
+    #[must_use]
+    pub fn from_env() -> Self {
+        let mode = match env::var("SCREENSAVER_MODE").as_deref() {
+            Err(_) | Ok("dim") => Mode::Dim,
+            Ok("blank") => Mode::Blank,
+            Ok("power_off") => Mode::PowerOff,
+            Ok("off") => Mode::Off,
+            Ok(other) => {
+                warn!(value = other, "unknown SCREENSAVER_MODE, dimming");
+                Mode::Dim
+            }
+        };
+        let idle_after = env::var("SCREENSAVER_IDLE_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .map_or(DEFAULT_IDLE, Duration::from_secs);
+        let fps = env::var("SCREENSAVER_FPS")
+            .ok()
+            .and_then(|fps| fps.parse().ok())
+            .filter(|fps| *fps > 0)
+            .unwrap_or(DEFAULT_SLEEP_FPS);
+        let schedule = match env::var("SCREENSAVER_HOURS") {
+            Err(_) => Schedule::Always,
+            Ok(hours) => Schedule::parse(&hours).unwrap_or_else(|| {
+                warn!(
+                    value = hours,
+                    "invalid SCREENSAVER_HOURS, applying at any time"
+                );
+                Schedule::Always
+            }),
+        };
+
+        Self {
+            mode,
+            idle_after,
+            sleep_frame: Duration::from_secs(1) / fps,
+            schedule,
+            last_activity: Instant::now(),
+            scheduled: None,
+            asleep: false,
+        }
+    }
+
+    fn scheduled(&mut self, now: Instant) -> bool {
+        match self.scheduled {
+            Some((applies, until)) if now < until => applies,
+            _ => {
+                let applies = self.schedule.applies_now();
+                self.scheduled = Some((applies, now + SCHEDULE_CHECK_INTERVAL));
+                applies
+            }
+        }
+    }
+
+    /// Sleeps or wakes the screen, called once a frame
+    ///
+    /// `active` is whether anything is happening at the door which should keep
+    /// the screen on, such as a tap being shown.
+    pub fn update(&mut self, active: bool) {
+        let now = Instant::now();
+        if WAKE.swap(false, Ordering::Relaxed) || active {
+            self.last_activity = now;
+        }
+        let sleep = self.mode != Mode::Off
+            && now.duration_since(self.last_activity) >= self.idle_after
+            && self.scheduled(now);
+        if sleep == self.asleep {
+            return;
+        }
+
+        self.asleep = sleep;
+        info!(asleep = sleep, "screensaver changed");
+        if self.mode == Mode::PowerOff {
+            set_display_power(!sleep);
+        }
+    }
+
+    /// Whether nothing should be drawn
+    #[must_use]
+    pub fn is_blank(&self) -> bool {
+        self.asleep && matches!(self.mode, Mode::Blank | Mode::PowerOff)
+    }
+
+    /// Dims everything drawn so far, if asleep in dim mode
+    pub fn draw(&self) {
+        if self.asleep && self.mode == Mode::Dim {
+            draw_rectangle(
+                0.0,
+                0.0,
+                screen_width(),
+                screen_height(),
+                BLACK_BG(DIM_OPACITY),
+            );
+        }
+    }
+
+    /// Holds the frame back while asleep, lowering the frame rate
+    pub fn throttle(&self) {
+        if self.asleep {
+            thread::sleep(self.sleep_frame);
+        }
+    }
+}
+
+fn set_display_power(on: bool) {
+    let state = if on { "on" } else { "off" };
+    match Command::new("swaymsg")
+        .args(["output", "*", "power", state])
+        .status()
+    {
+        Ok(status) if status.success() => {}
+        Ok(status) => warn!(%status, "swaymsg failed to turn the display {state}"),
+        Err(e) => warn!(error = %e, "failed to run swaymsg to turn the display {state}"),
+    }
+}
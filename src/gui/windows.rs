@@ -1,9 +1,14 @@
 use macroquad::prelude::*;
 
-use crate::gui::MessageOpacities;
-use crate::gui::colors::{BLACK_BG, YELLOW_ACCENT};
+use chrono::{DateTime, Local, Utc};
+
+use crate::gui::colors::{BLACK_BG, RED_CL, WHITE_CL, YELLOW_ACCENT};
 use crate::gui::constants::{OPACITY_MAX, OPACITY_MIN, TEXT_MARGIN};
 use crate::gui::font_engine::{Point, draw_text};
+use crate::gui::qr::QrImage;
+use crate::gui::{MessageDetails, MessageOpacities};
+use crate::health::{Status, SubsystemHealth};
+use crate::mode::OpenHouse;
 
 #[allow(clippy::cast_possible_truncation)]
 fn opacity_to_u8(opacity: f32) -> u8 {
@@ -19,7 +24,37 @@ fn get_desc_text_size() -> u16 {
     if screen_height() < 720.0 { 32 } else { 48 }
 }
 
-pub fn draw_message_windows(opacities: &MessageOpacities, font: &Font) {
+/// Describes when the door next opens, e.g. "Doors open again Friday at 8:00 PM."
+fn describe_next_open(next_open: Option<i64>) -> String {
+    let Some(next_open) = next_open.and_then(|t| DateTime::from_timestamp(t, 0)) else {
+        return String::from("Please contact an organizer to get in.");
+    };
+    let next_open = next_open.with_timezone(&Local);
+    let day = if next_open.date_naive() == Local::now().date_naive() {
+        String::from("today")
+    } else {
+        next_open.format("%A").to_string()
+    };
+    format!(
+        "Doors open again {day} at {}.",
+        next_open.format("%-I:%M %p")
+    )
+}
+
+/// Counts down to when scanning may be tried again
+fn describe_cooldown(retry_at: i64) -> String {
+    match retry_at - Utc::now().timestamp() {
+        ..=0 => String::from("You can scan your passport again now."),
+        1 => String::from("Please wait 1 second before scanning again."),
+        secs @ 2..=90 => format!("Please wait {secs} seconds before scanning again."),
+        secs => format!(
+            "Please wait {} minutes before scanning again.",
+            (secs + 59) / 60
+        ),
+    }
+}
+
+pub fn draw_message_windows(opacities: &MessageOpacities, details: &MessageDetails, font: &Font) {
     draw_welcome_window(opacity_to_u8(opacities.welcome), font);
     draw_accepted_window(opacity_to_u8(opacities.accepted), font);
     draw_error_window(
@@ -46,6 +81,24 @@ pub fn draw_message_windows(opacities: &MessageOpacities, font: &Font) {
         "Button pusher not ready yet!",
         "Try again after a minute or contact an organizer.",
     );
+    draw_error_window(
+        opacity_to_u8(opacities.outside_hours),
+        font,
+        "Outside of open hours!",
+        &describe_next_open(details.next_open),
+    );
+    draw_error_window(
+        opacity_to_u8(opacities.lockdown),
+        font,
+        "Door locked down!",
+        "Entry is suspended. Please contact an organizer.",
+    );
+    draw_error_window(
+        opacity_to_u8(opacities.rate_limited),
+        font,
+        "Too many attempts!",
+        &describe_cooldown(details.retry_at),
+    );
 }
 
 fn draw_message_box(opacity: u8, margin_percentage: f32, content_percentage: f32) {
@@ -171,3 +224,186 @@ fn draw_error_window(opacity: u8, font: &Font, title: &str, subtitle: &str) {
         1.0,
     );
 }
+
+/// Shows a banner along the top of the screen during lockdown or open house
+pub fn draw_mode_banner(locked_down: bool, open_house: Option<OpenHouse>, font: &Font) {
+    let (text, background, foreground) = if locked_down {
+        (
+            String::from("Lockdown — the door will not open"),
+            RED_CL,
+            WHITE_CL(255),
+        )
+    } else if let Some(open_house) = open_house {
+        let until = DateTime::from_timestamp(open_house.until, 0)
+            .map(|until| {
+                until
+                    .with_timezone(&Local)
+                    .format(" until %-I:%M %p")
+                    .to_string()
+            })
+            .unwrap_or_default();
+        (
+            format!("Doors open — come on in!{until}"),
+            YELLOW_ACCENT(255),
+            BLACK_BG(255),
+        )
+    } else {
+        return;
+    };
+
+    let banner_height = 64.0;
+    draw_rectangle(0.0, 0.0, screen_width(), banner_height, background);
+    let _ = draw_text(
+        &text,
+        Point::new(TEXT_MARGIN / 2.0, 12.0),
+        screen_width() - TEXT_MARGIN,
+        foreground,
+        font,
+        32,
+        1.0,
+    );
+}
+
+/// Lists stalled and degraded subsystems along the bottom of the screen
+pub fn draw_subsystem_warnings(subsystems: &[SubsystemHealth], font: &Font) {
+    let warnings: Vec<String> = subsystems
+        .iter()
+        .filter_map(|s| {
+            if !s.is_alive() {
+                Some(format!("{}: not responding", s.subsystem))
+            } else if let Status::Degraded(reason) = &s.status {
+                Some(format!("{}: {reason}", s.subsystem))
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    if warnings.is_empty() {
+        return;
+    }
+
+    let font_size = 20;
+    let line_height = 28.0;
+    #[allow(clippy::cast_precision_loss)]
+    let box_height = line_height * warnings.len() as f32 + 8.0;
+    let top = screen_height() - box_height;
+
+    draw_rectangle(0.0, top, screen_width(), box_height, BLACK_BG(200));
+    draw_rectangle(0.0, top, screen_width(), 2.0, RED_CL);
+
+    for (i, warning) in warnings.iter().enumerate() {
+        #[allow(clippy::cast_precision_loss)]
+        let y = top + 4.0 + line_height * i as f32;
+        let _ = draw_text(
+            warning,
+            Point::new(TEXT_MARGIN / 2.0, y),
+            screen_width() - TEXT_MARGIN,
+            RED_CL,
+            font,
+            font_size,
+            1.0,
+        );
+    }
+}
+
+/// Shows the presence code guests redeem their pass with in the bottom right corner
+///
+/// `qr` links to the guest pass page with the code filled in, if one is configured.
+pub fn draw_guest_pass_panel(qr: Option<&QrImage>, presence_code: &str, font: &Font) {
+    let panel_width = 256.0;
+    let qr_size = panel_width - 32.0;
+    let text_height = 96.0;
+    let panel_height = text_height + if qr.is_some() { qr_size + 16.0 } else { 0.0 };
+    let left = screen_width() - panel_width - TEXT_MARGIN / 2.0;
+    // Kept clear of the subsystem warnings along the bottom
+    let top = screen_height() - panel_height - 96.0;
+
+    draw_rectangle(left, top, panel_width, panel_height, BLACK_BG(230));
+    draw_rectangle(left, top, panel_width, 2.0, YELLOW_ACCENT(255));
+
+    let mut y = top + 16.0;
+    if let Some(qr) = qr {
+        qr.draw(left + 16.0, y, qr_size, 255);
+        y += qr_size + 8.0;
+    }
+    let _ = draw_text(
+        "Have a guest pass? Enter",
+        Point::new(left + 16.0, y),
+        panel_width - 32.0,
+        WHITE_CL(255),
+        font,
+        20,
+        1.0,
+    );
+    let _ = draw_text(
+        presence_code,
+        Point::new(left + 16.0, y + 28.0),
+        panel_width - 32.0,
+        YELLOW_ACCENT(255),
+        font,
+        40,
+        1.0,
+    );
+}
+
+/// Shows a QR code and its caption below the message box in the bottom left corner
+pub fn draw_link_qr(qr: &QrImage, caption: &str, opacity: f32, font: &Font) {
+    let opacity = opacity_to_u8(opacity);
+    if opacity == 0 {
+        return;
+    }
+
+    let size = screen_height() * 0.16;
+    let left = TEXT_MARGIN / 2.0;
+    let top = screen_height() - size - TEXT_MARGIN / 2.0;
+    qr.draw(left, top, size, opacity);
+    let _ = draw_text(
+        caption,
+        Point::new(left + size + TEXT_MARGIN / 2.0, top + size / 2.0 - 24.0),
+        screen_width() / 2.0,
+        WHITE_CL(opacity),
+        font,
+        24,
+        1.0,
+    );
+}
+
+/// Quietly notes a low door module battery below the camera indicator, for organizers
+pub fn draw_low_battery(percent: u8, font: &Font) {
+    let width = 224.0;
+    let height = 32.0;
+    let left = screen_width() - width - TEXT_MARGIN / 2.0;
+    let top = 136.0;
+
+    draw_rectangle(left, top, width, height, BLACK_BG(160));
+    let _ = draw_text(
+        &format!("Door battery low ({percent}%)"),
+        Point::new(left + 12.0, top + 6.0),
+        width - 24.0,
+        YELLOW_ACCENT(200),
+        font,
+        18,
+        1.0,
+    );
+}
+
+/// Shows that the camera is streaming in the top right corner, below the mode banner
+pub fn draw_camera_indicator(font: &Font) {
+    let width = 224.0;
+    let height = 48.0;
+    let left = screen_width() - width - TEXT_MARGIN / 2.0;
+    let top = 80.0;
+
+    draw_rectangle(left, top, width, height, BLACK_BG(230));
+    draw_circle(left + 24.0, top + height / 2.0, 10.0, RED_CL);
+    let _ = draw_text(
+        "Camera active",
+        Point::new(left + 48.0, top + 10.0),
+        width - 56.0,
+        WHITE_CL(255),
+        font,
+        24,
+        1.0,
+    );
+}
@@ -0,0 +1,101 @@
+//! Confirmation that ada-pusher actually pressed the button
+//!
+//! ada-pusher reports each step of an actuation on its status characteristic,
+//! first `pressed` and then `released`. An open only counts once both arrive,
+//! so a command lost in the air is retried instead of reported as an open door.
+
+use std::env;
+use std::error::Error;
+use std::time::Duration;
+
+use btleplug::api::ValueNotification;
+use futures::{Stream, StreamExt};
+use tokio::time;
+use tracing::warn;
+use uuid::{Uuid, uuid};
+
+/// Status characteristic ada-pusher notifies actuation steps on
+pub const STATUS_UUID: Uuid = uuid!("7e783541-f3ab-431f-adff-566767b8bb31");
+/// Time for ada-pusher to press and release when `ADA_PUSHER_CONFIRM_TIMEOUT_MS` is unset
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Steps reported for an actuation, in order
+const STEPS: [&str; 2] = ["pressed", "released"];
+
+/// Reads how long to wait for a press to be confirmed, warning about and ignoring invalid values
+pub fn timeout_from_env() -> Duration {
+    let Ok(millis) = env::var("ADA_PUSHER_CONFIRM_TIMEOUT_MS") else {
+        return DEFAULT_TIMEOUT;
+    };
+    match millis.parse() {
+        Ok(millis) if millis > 0 => Duration::from_millis(millis),
+        _ => {
+            warn!(
+                value = millis,
+                "invalid ADA_PUSHER_CONFIRM_TIMEOUT_MS, ignoring"
+            );
+            DEFAULT_TIMEOUT
+        }
+    }
+}
+
+/// Reads whether `ADA_PUSHER_REQUIRE_CONFIRM` refuses to open with firmware
+/// which cannot confirm presses
+pub fn required_from_env() -> bool {
+    match env::var("ADA_PUSHER_REQUIRE_CONFIRM").as_deref() {
+        Err(_) | Ok("false" | "0") => false,
+        Ok("true" | "1") => true,
+        Ok(other) => {
+            warn!(
+                value = other,
+                "invalid ADA_PUSHER_REQUIRE_CONFIRM, ignoring"
+            );
+            false
+        }
+    }
+}
+
+async fn wait_for_steps(
+    notifications: &mut (impl Stream<Item = ValueNotification> + Unpin),
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    for step in STEPS {
+        loop {
+            let notification = notifications
+                .next()
+                .await
+                .ok_or("ada-pusher stopped sending notifications")?;
+            if notification.uuid != STATUS_UUID {
+                continue;
+            }
+            match notification.value.as_slice() {
+                value if value == step.as_bytes() => break,
+                // A late report from an earlier actuation
+                b"released" => {}
+                other => {
+                    return Err(
+                        format!("ada-pusher reported {}", String::from_utf8_lossy(other)).into(),
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Waits for ada-pusher to report pressing and releasing the button
+///
+/// `notifications` must have been taken before the command was written, so a
+/// fast press is not missed.
+///
+/// # Errors
+///
+/// Will error if the press is not confirmed within `timeout`, or ada-pusher
+/// reports something else, such as a failure
+pub async fn wait_for_press(
+    notifications: &mut (impl Stream<Item = ValueNotification> + Unpin),
+    timeout: Duration,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    time::timeout(timeout, wait_for_steps(notifications))
+        .await
+        .map_err(|_| format!("ada-pusher did not confirm the press within {timeout:?}"))?
+}
@@ -0,0 +1,310 @@
+mod confirm;
+mod monitor;
+mod readout;
+
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::process::Command;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use btleplug::api::{
+    BDAddr, Central, CharPropFlags, Characteristic, Manager as _, Peripheral as _,
+    PeripheralProperties, ScanFilter, WriteType, bleuuid::uuid_from_u16,
+};
+use btleplug::platform::Manager;
+use btleplug::platform::{Adapter, Peripheral};
+use tokio::{
+    task::{self, JoinHandle},
+    time,
+};
+use tracing::{error, info, warn};
+use uuid::{Uuid, uuid};
+
+use crate::alerts::{self, AlertKind};
+use crate::hardware::door::{OpenModule, link};
+use crate::metrics;
+
+pub struct AdaPusher {
+    device: Peripheral,
+    /// Watches the link and reconnects in the background, stopped with the module
+    monitor: JoinHandle<()>,
+    /// How long to wait for a press to be confirmed
+    confirm_timeout: Duration,
+    /// Whether to refuse to open with firmware which cannot confirm presses
+    require_confirm: bool,
+}
+
+const ADA_PUSHER_COMMAND_UUID: Uuid = uuid!("7e783540-f3ab-431f-adff-566767b8bb31");
+
+/// Which device is ours, from `ADA_PUSHER_ADDRESS` and `ADA_PUSHER_SERVICE_UUID`
+///
+/// With neither set, any device with `ada-pusher` or `nimble` in its name
+/// matches, which may be someone else's device.
+#[derive(Debug, Clone, Copy)]
+struct Target {
+    address: Option<BDAddr>,
+    service: Option<Uuid>,
+}
+
+impl Target {
+    /// Reads the target, warning about and ignoring invalid values
+    fn from_env() -> Self {
+        let address = env::var("ADA_PUSHER_ADDRESS").ok().and_then(|address| {
+            let parsed = address.parse().ok();
+            if parsed.is_none() {
+                warn!(value = address, "invalid ADA_PUSHER_ADDRESS, ignoring");
+            }
+            parsed
+        });
+        let service = env::var("ADA_PUSHER_SERVICE_UUID")
+            .ok()
+            .and_then(|service| {
+                let parsed = parse_service(&service);
+                if parsed.is_none() {
+                    warn!(value = service, "invalid ADA_PUSHER_SERVICE_UUID, ignoring");
+                }
+                parsed
+            });
+        if address.is_none() && service.is_none() {
+            warn!("no ada-pusher address or service set, matching any device by name");
+        }
+        Self { address, service }
+    }
+
+    fn scan_filter(self) -> ScanFilter {
+        ScanFilter {
+            services: self.service.into_iter().collect(),
+        }
+    }
+
+    fn matches(self, properties: &PeripheralProperties) -> bool {
+        if self.address.is_none() && self.service.is_none() {
+            return properties
+                .local_name
+                .as_ref()
+                .is_some_and(|name| name.contains("ada-pusher") || name.contains("nimble"));
+        }
+        self.address
+            .is_none_or(|address| properties.address == address)
+            && self
+                .service
+                .is_none_or(|service| properties.services.contains(&service))
+    }
+}
+
+/// Parses a service UUID, either in full or as a 16-bit short UUID such as `ADAD`
+fn parse_service(value: &str) -> Option<Uuid> {
+    let short = value.strip_prefix("0x").unwrap_or(value);
+    if short.len() == 4 {
+        return u16::from_str_radix(short, 16).ok().map(uuid_from_u16);
+    }
+    Uuid::parse_str(value).ok()
+}
+
+/// More than one device matched the target, and picking either could mean
+/// actuating someone else's device
+#[derive(Debug)]
+struct AmbiguousMatch(Vec<BDAddr>);
+
+impl fmt::Display for AmbiguousMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let addresses: Vec<String> = self.0.iter().map(ToString::to_string).collect();
+        write!(
+            f,
+            "{} devices match as ada-pusher ({}), set ADA_PUSHER_ADDRESS to pick one",
+            self.0.len(),
+            addresses.join(", ")
+        )
+    }
+}
+
+impl Error for AmbiguousMatch {}
+
+/// Addresses of the devices bonded with this machine, as listed by `bluetoothctl`
+async fn bonded_addresses() -> Vec<BDAddr> {
+    let output = match task::spawn_blocking(|| {
+        Command::new("bluetoothctl")
+            .args(["devices", "Bonded"])
+            .output()
+    })
+    .await
+    {
+        Ok(result) => result,
+        Err(e) => Err(io::Error::other(e)),
+    };
+    match output {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| line.strip_prefix("Device ")?.split_whitespace().next())
+            .filter_map(|address| address.parse().ok())
+            .collect(),
+        Ok(output) => {
+            warn!(status = %output.status, "bluetoothctl failed to list bonded devices");
+            Vec::new()
+        }
+        Err(e) => {
+            warn!(error = %e, "failed to run bluetoothctl to list bonded devices");
+            Vec::new()
+        }
+    }
+}
+
+impl AdaPusher {
+    pub async fn new() -> Self {
+        let target = Target::from_env();
+        let mut alerted = false;
+        loop {
+            match Self::try_init(target).await {
+                Ok(pusher) => return pusher,
+                Err(e) => {
+                    metrics::record_ble_connect_failure();
+                    if e.is::<AmbiguousMatch>() {
+                        error!(error = %e, "refusing to guess which device is ada-pusher");
+                        // Raised once, rather than on every retry
+                        if !alerted {
+                            alerts::raise(AlertKind::AmbiguousDoorModule, e.to_string());
+                            alerted = true;
+                        }
+                    } else {
+                        eprintln!("ada-pusher init failed: {e}, retrying in 5s...");
+                    }
+                    time::sleep(Duration::from_secs(5)).await;
+                }
+            }
+        }
+    }
+
+    async fn try_init(target: Target) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let manager = Manager::new().await?;
+        let adapters = manager.adapters().await?;
+        let central = adapters.into_iter().nth(0).ok_or("no adapters found")?;
+
+        central.start_scan(target.scan_filter()).await?;
+        println!("Scanning for BLE devices...");
+        time::sleep(Duration::from_secs(5)).await;
+
+        let device = Self::find_ada_pusher_device(&central, target).await?;
+        info!(address = %device.address(), "ada-pusher found");
+
+        monitor::connect(&device).await?;
+        println!("Finished setting up ada-pusher, discovered characteristics");
+        monitor::set_connected(true);
+
+        let monitor = task::spawn(monitor::watch(central, device.clone()));
+        Ok(AdaPusher {
+            device,
+            monitor,
+            confirm_timeout: confirm::timeout_from_env(),
+            require_confirm: confirm::required_from_env(),
+        })
+    }
+
+    /// Finds a characteristic, as discovered on the latest connection
+    fn find_char(&self, uuid: Uuid) -> Option<Characteristic> {
+        self.device
+            .characteristics()
+            .into_iter()
+            .find(|c| uuid == c.uuid)
+    }
+
+    /// Finds the one device matching the target, preferring bonded devices
+    ///
+    /// # Errors
+    ///
+    /// Will error if nothing matches, or if more than one device matches and
+    /// bonding does not settle which
+    async fn find_ada_pusher_device(
+        central: &Adapter,
+        target: Target,
+    ) -> Result<Peripheral, Box<dyn Error + Send + Sync>> {
+        let mut candidates = Vec::new();
+        for p in central.peripherals().await? {
+            // Devices which have gone away may have no properties left
+            if let Some(properties) = p.properties().await?
+                && target.matches(&properties)
+            {
+                candidates.push(p);
+            }
+        }
+
+        if candidates.len() > 1 {
+            let bonded = bonded_addresses().await;
+            let (preferred, others): (Vec<_>, Vec<_>) = candidates
+                .into_iter()
+                .partition(|p| bonded.contains(&p.address()));
+            candidates = if preferred.is_empty() {
+                others
+            } else {
+                preferred
+            };
+        }
+
+        match candidates.len() {
+            0 => Err("ada-pusher not found during scan".into()),
+            1 => Ok(candidates.remove(0)),
+            _ => Err(Box::new(AmbiguousMatch(
+                candidates.iter().map(Peripheral::address).collect(),
+            ))),
+        }
+    }
+}
+
+#[async_trait]
+impl OpenModule for AdaPusher {
+    async fn open_door(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let command = self
+            .find_char(ADA_PUSHER_COMMAND_UUID)
+            .ok_or("failed to find command characteristic")?;
+        let confirmable = self.find_char(confirm::STATUS_UUID).is_some();
+        // Checked before writing, so a press which cannot be confirmed never happens
+        if !confirmable && self.require_confirm {
+            return Err("ada-pusher cannot confirm presses, not opening".into());
+        }
+        // Taken before writing, so a fast press is not missed
+        let mut notifications = self.device.notifications().await?;
+
+        println!("Sending open command over BLE...");
+        let write_type = if command.properties.contains(CharPropFlags::WRITE) {
+            WriteType::WithResponse
+        } else {
+            WriteType::WithoutResponse
+        };
+        self.device.write(&command, b"open", write_type).await?;
+
+        if !confirmable {
+            info!("command sent over BLE, but this firmware cannot confirm presses");
+            return Ok(());
+        }
+        confirm::wait_for_press(&mut notifications, self.confirm_timeout).await?;
+        info!("ada-pusher confirmed the press");
+        Ok(())
+    }
+
+    async fn prepare(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if self.device.is_connected().await? {
+            return Ok(());
+        }
+        info!("reconnecting to ada-pusher ahead of a tap");
+        monitor::connect(&self.device).await?;
+        monitor::set_connected(true);
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // Otherwise the monitor would reconnect straight away
+        self.monitor.abort();
+        self.device.disconnect().await?;
+        Ok(())
+    }
+}
+
+impl Drop for AdaPusher {
+    fn drop(&mut self) {
+        self.monitor.abort();
+        // Dropping the module is announced by the door task, which knows why
+        link::update(|link| link.connected = false);
+    }
+}
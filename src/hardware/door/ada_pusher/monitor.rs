@@ -0,0 +1,172 @@
+//! Watches the BLE link to ada-pusher, reconnecting as soon as it drops
+//!
+//! Without this, a dropped link is only noticed when an open fails every
+//! retry, by which point someone is standing at a closed door.
+
+use std::pin::Pin;
+use std::time::Duration;
+
+use btleplug::api::{Central, CentralEvent, Peripheral as _};
+use btleplug::platform::{Adapter, Peripheral};
+use chrono::Utc;
+use futures::stream::{self, Stream, StreamExt};
+use tokio::time::{self, Instant};
+use tracing::{error, info, warn};
+
+use super::{confirm, readout};
+use crate::alerts::{self, AlertKind};
+use crate::events::{self, Event, Link};
+use crate::hardware::door::link;
+use crate::health::{self, Status, Subsystem};
+use crate::metrics;
+use crate::shutdown;
+
+/// How often the link is checked, in case a disconnect event is missed
+const LINK_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// How often the battery and diagnostics are read again while connected
+const DIAGNOSTICS_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// Connects to ada-pusher, follows its status and reads its diagnostics
+pub async fn connect(device: &Peripheral) -> btleplug::Result<()> {
+    device.connect().await?;
+    device.discover_services().await?;
+
+    // Subscriptions do not survive reconnecting
+    match device
+        .characteristics()
+        .into_iter()
+        .find(|c| c.uuid == confirm::STATUS_UUID)
+    {
+        Some(status) => device.subscribe(&status).await?,
+        None if confirm::required_from_env() => {
+            error!("ada-pusher has no status characteristic, refusing to open the door");
+            alerts::raise(
+                AlertKind::UnconfirmableDoorModule,
+                String::from(
+                    "ada-pusher cannot confirm presses and ADA_PUSHER_REQUIRE_CONFIRM is set, so the door will not open",
+                ),
+            );
+        }
+        None => {
+            warn!("ada-pusher has no status characteristic, presses cannot be confirmed");
+        }
+    }
+
+    readout::read_diagnostics(device).await;
+    Ok(())
+}
+
+/// Records that ada-pusher was heard from, with its signal strength if known
+async fn mark_seen(device: &Peripheral) {
+    let rssi = device
+        .properties()
+        .await
+        .ok()
+        .flatten()
+        .and_then(|properties| properties.rssi);
+    link::update(|link| {
+        link.last_seen = Some(Utc::now().timestamp());
+        if rssi.is_some() {
+            link.rssi = rssi;
+        }
+    });
+}
+
+/// Records whether ada-pusher is connected, announcing when that changes
+pub fn set_connected(connected: bool) {
+    let changed = link::update(|link| {
+        if connected {
+            link.last_seen = Some(Utc::now().timestamp());
+        }
+        std::mem::replace(&mut link.connected, connected) != connected
+    });
+    if !changed {
+        return;
+    }
+
+    if connected {
+        info!("ada-pusher link is up");
+        health::set_status(Subsystem::Door, Status::Ready);
+    } else {
+        warn!("ada-pusher link dropped, reconnecting");
+        health::set_status(
+            Subsystem::Door,
+            Status::Degraded(String::from("ada-pusher disconnected, reconnecting")),
+        );
+    }
+    events::publish(Event::Connectivity {
+        link: Link::DoorModule,
+        connected,
+    });
+}
+
+/// Reconnects until it works, returning whether it did before shutdown
+async fn reconnect(device: &Peripheral) -> bool {
+    while !shutdown::is_triggered() {
+        match connect(device).await {
+            Ok(()) => return true,
+            Err(e) => {
+                metrics::record_ble_connect_failure();
+                warn!(error = %e, "failed to reconnect to ada-pusher");
+            }
+        }
+        tokio::select! {
+            () = time::sleep(RECONNECT_DELAY) => {}
+            () = shutdown::wait() => {}
+        }
+    }
+    false
+}
+
+/// Follows connection events for ada-pusher, until shutdown or the module is dropped
+pub async fn watch(central: Adapter, device: Peripheral) {
+    let mut events: Pin<Box<dyn Stream<Item = CentralEvent> + Send>> = match central.events().await
+    {
+        Ok(events) => events,
+        Err(e) => {
+            warn!(error = %e, "failed to follow BLE events, only polling the ada-pusher link");
+            Box::pin(stream::empty())
+        }
+    };
+    let mut check = time::interval(LINK_CHECK_INTERVAL);
+    // Connecting has just read them
+    let mut diagnostics =
+        time::interval_at(Instant::now() + DIAGNOSTICS_INTERVAL, DIAGNOSTICS_INTERVAL);
+    let id = device.id();
+
+    loop {
+        let connected = tokio::select! {
+            Some(event) = events.next() => match event {
+                CentralEvent::DeviceDisconnected(event_id) if event_id == id => false,
+                CentralEvent::DeviceConnected(event_id) | CentralEvent::DeviceUpdated(event_id)
+                    if event_id == id =>
+                {
+                    mark_seen(&device).await;
+                    continue;
+                }
+                _ => continue,
+            },
+            _ = check.tick() => device.is_connected().await.unwrap_or(false),
+            _ = diagnostics.tick() => {
+                if device.is_connected().await.unwrap_or(false) {
+                    readout::read_diagnostics(&device).await;
+                }
+                continue;
+            }
+            () = shutdown::wait() => return,
+        };
+
+        if connected {
+            // Also picks up reconnects made elsewhere, such as ahead of a tap
+            set_connected(true);
+            mark_seen(&device).await;
+            continue;
+        }
+        set_connected(false);
+        if !reconnect(&device).await {
+            return;
+        }
+        set_connected(true);
+    }
+}
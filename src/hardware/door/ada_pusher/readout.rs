@@ -0,0 +1,64 @@
+//! Reads ada-pusher's battery level, firmware version and diagnostics
+
+use btleplug::api::{Peripheral as _, bleuuid::uuid_from_u16};
+use btleplug::platform::Peripheral;
+use chrono::Utc;
+use tracing::{info, warn};
+use uuid::{Uuid, uuid};
+
+use crate::hardware::door::diagnostics::{self, ModuleDiagnostics};
+
+/// Battery Level, in the standard Battery Service
+const BATTERY_LEVEL_UUID: Uuid = uuid_from_u16(0x2A19);
+/// Firmware Revision String, in the standard Device Information service
+const FIRMWARE_REVISION_UUID: Uuid = uuid_from_u16(0x2A26);
+/// ada-pusher's own diagnostics, such as its uptime and press count
+const DIAGNOSTICS_UUID: Uuid = uuid!("7e783542-f3ab-431f-adff-566767b8bb31");
+
+/// Reads a characteristic, or `None` if the firmware does not have it
+async fn read(device: &Peripheral, uuid: Uuid) -> Option<Vec<u8>> {
+    let characteristic = device
+        .characteristics()
+        .into_iter()
+        .find(|c| c.uuid == uuid)?;
+    match device.read(&characteristic).await {
+        Ok(value) => Some(value),
+        Err(e) => {
+            warn!(%uuid, error = %e, "failed to read ada-pusher characteristic");
+            None
+        }
+    }
+}
+
+fn text(value: &[u8]) -> String {
+    // Some firmware pads strings with nulls
+    String::from_utf8_lossy(value)
+        .trim_end_matches('\0')
+        .to_string()
+}
+
+/// Reads what ada-pusher reports about itself, for status reports and the screen
+pub async fn read_diagnostics(device: &Peripheral) {
+    let battery_percent = read(device, BATTERY_LEVEL_UUID)
+        .await
+        .and_then(|value| value.first().copied())
+        .filter(|percent| *percent <= 100);
+    let firmware_version = read(device, FIRMWARE_REVISION_UUID)
+        .await
+        .map(|value| text(&value));
+    let custom = read(device, DIAGNOSTICS_UUID).await.map(|value| {
+        serde_json::from_slice(&value).unwrap_or_else(|_| serde_json::Value::String(text(&value)))
+    });
+
+    info!(
+        battery_percent,
+        firmware_version = firmware_version.as_deref(),
+        "read ada-pusher diagnostics"
+    );
+    diagnostics::set(ModuleDiagnostics {
+        battery_percent,
+        firmware_version,
+        custom,
+        read_at: Utc::now().timestamp(),
+    });
+}
@@ -0,0 +1,71 @@
+//! Battery, firmware and diagnostics reported by the door module
+//!
+//! ada-pusher is read after every connection and periodically while
+//! connected. The battery counts as low below `LOW_BATTERY_PERCENT`
+//! (default 20).
+
+use std::env;
+use std::sync::{LazyLock, Mutex, PoisonError};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// Battery level warned about when `LOW_BATTERY_PERCENT` is unset
+pub const DEFAULT_LOW_BATTERY_PERCENT: u8 = 20;
+
+static DIAGNOSTICS: LazyLock<Mutex<Option<ModuleDiagnostics>>> = LazyLock::new(Mutex::default);
+
+static LOW_BATTERY_PERCENT: LazyLock<u8> = LazyLock::new(|| {
+    let Ok(percent) = env::var("LOW_BATTERY_PERCENT") else {
+        return DEFAULT_LOW_BATTERY_PERCENT;
+    };
+    match percent.parse() {
+        Ok(percent) if percent <= 100 => percent,
+        _ => {
+            warn!(value = percent, "invalid LOW_BATTERY_PERCENT, ignoring");
+            DEFAULT_LOW_BATTERY_PERCENT
+        }
+    }
+});
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModuleDiagnostics {
+    /// Battery level from 0 to 100, from the standard Battery Service
+    pub battery_percent: Option<u8>,
+    /// Firmware revision, from the standard Device Information service
+    pub firmware_version: Option<String>,
+    /// The module's own diagnostics, as JSON if it sends JSON and as text otherwise
+    pub custom: Option<serde_json::Value>,
+    /// When these were read, as a Unix timestamp
+    pub read_at: i64,
+}
+
+/// What the door module last reported, or `None` if it has not been read
+#[must_use]
+pub fn state() -> Option<ModuleDiagnostics> {
+    DIAGNOSTICS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+}
+
+/// The battery level, if the door module last reported it as low
+#[must_use]
+pub fn low_battery() -> Option<u8> {
+    DIAGNOSTICS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .as_ref()
+        .and_then(|diagnostics| diagnostics.battery_percent)
+        .filter(|percent| *percent < *LOW_BATTERY_PERCENT)
+}
+
+#[cfg(feature = "ada_pusher")]
+pub(super) fn set(diagnostics: ModuleDiagnostics) {
+    if let Some(percent) = diagnostics.battery_percent
+        && percent < *LOW_BATTERY_PERCENT
+    {
+        warn!(percent, "door module battery is low");
+    }
+    *DIAGNOSTICS.lock().unwrap_or_else(PoisonError::into_inner) = Some(diagnostics);
+}
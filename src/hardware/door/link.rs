@@ -0,0 +1,33 @@
+//! State of the wireless link to the door module
+//!
+//! Modules which keep a connection open, like ada-pusher over BLE, report here
+//! whether they are connected and when they were last heard from. Modules
+//! without a link, like the dummy module, report nothing.
+
+use std::sync::{LazyLock, Mutex, PoisonError};
+
+use serde::{Deserialize, Serialize};
+
+static LINK: LazyLock<Mutex<Option<ModuleLink>>> = LazyLock::new(Mutex::default);
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ModuleLink {
+    pub connected: bool,
+    /// Signal strength in dBm, from the last advertisement heard
+    pub rssi: Option<i16>,
+    /// When the module was last heard from, as a Unix timestamp
+    pub last_seen: Option<i64>,
+}
+
+/// The link to the door module, or `None` if the module has not reported one
+#[must_use]
+pub fn state() -> Option<ModuleLink> {
+    *LINK.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Updates the link to the door module, starting from a disconnected link
+#[cfg(feature = "ada_pusher")]
+pub(super) fn update<R>(f: impl FnOnce(&mut ModuleLink) -> R) -> R {
+    let mut link = LINK.lock().unwrap_or_else(PoisonError::into_inner);
+    f(link.get_or_insert_with(ModuleLink::default))
+}
@@ -1,40 +1,85 @@
 #[cfg(feature = "ada_pusher")]
 mod ada_pusher;
+pub mod diagnostics;
 #[cfg(not(feature = "ada_pusher"))]
 mod dummy;
+pub mod link;
+mod request;
+
+pub use request::{OpenRequest, OpenSource};
 
 use std::error::Error;
-use std::time::Duration;
+use std::future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
 use tokio::{
-    sync::mpsc::{UnboundedSender, unbounded_channel},
-    task, time,
+    sync::Mutex,
+    sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel},
+    sync::oneshot,
+    task::{self, JoinHandle},
+    time::{self, Interval, MissedTickBehavior},
 };
+use tracing::{info, warn};
 
+use crate::audit::{self, AuditEvent};
+use crate::camera::motion::{self, PresenceKind};
 use crate::enums::AuthState;
+use crate::events::{self, Event, Link};
 #[cfg(feature = "ada_pusher")]
 use crate::hardware::door::ada_pusher::AdaPusher;
 #[cfg(not(feature = "ada_pusher"))]
 use crate::hardware::door::dummy::Dummy;
+use crate::health::{self, Status, Subsystem};
+use crate::metrics;
+use crate::mode::{self, OpenHouse};
+use crate::shutdown;
+use crate::supervisor::supervise;
 
 const OPEN_DOOR_MAX_RETRIES: u32 = 3;
 const OPEN_DOOR_RETRY_DELAY: Duration = Duration::from_secs(1);
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+/// Open requests wait while the module prepares, so it is given up on after this
+const PREPARE_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub struct DoorOpener {
-    tx: UnboundedSender<()>,
+    tx: UnboundedSender<OpenRequest>,
+    task: JoinHandle<()>,
 }
 
 #[async_trait]
 trait OpenModule {
     async fn open_door(&mut self) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    /// Gets ready to open quickly, such as by reconnecting, when someone arrives
+    async fn prepare(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Ok(())
+    }
+
+    /// Releases the underlying hardware before shutting down
+    async fn disconnect(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Ok(())
+    }
 }
 
-async fn open_with_retry(module: &mut (dyn OpenModule + Send)) -> bool {
+async fn open_with_retry(module: &mut (dyn OpenModule + Send), source: OpenSource) -> bool {
+    let started = Instant::now();
     for attempt in 1..=OPEN_DOOR_MAX_RETRIES {
         match module.open_door().await {
-            Ok(()) => return true,
+            Ok(()) => {
+                motion::answered();
+                metrics::record_door_open_attempt(true);
+                metrics::record_door_open(true, started.elapsed());
+                audit::record(&AuditEvent::DoorOpen {
+                    success: true,
+                    source: source.name().into(),
+                });
+                events::publish(Event::DoorActuated { success: true });
+                return true;
+            }
             Err(e) => {
+                metrics::record_door_open_attempt(false);
                 eprintln!("open_door failed (attempt {attempt}/{OPEN_DOOR_MAX_RETRIES}): {e}");
                 if attempt < OPEN_DOOR_MAX_RETRIES {
                     time::sleep(OPEN_DOOR_RETRY_DELAY).await;
@@ -43,12 +88,19 @@ async fn open_with_retry(module: &mut (dyn OpenModule + Send)) -> bool {
         }
     }
     eprintln!("open_door failed after {OPEN_DOOR_MAX_RETRIES} attempts, re-initializing module");
+    metrics::record_door_open(false, started.elapsed());
+    metrics::record_door_module_reinit();
+    audit::record(&AuditEvent::DoorOpen {
+        success: false,
+        source: source.name().into(),
+    });
+    events::publish(Event::DoorActuated { success: false });
     false
 }
 
 #[cfg(feature = "ada_pusher")]
-fn spawn_ada_pusher_init() -> tokio::sync::oneshot::Receiver<Box<dyn OpenModule + Send>> {
-    let (init_tx, init_rx) = tokio::sync::oneshot::channel::<Box<dyn OpenModule + Send>>();
+fn spawn_module_init() -> oneshot::Receiver<Box<dyn OpenModule + Send>> {
+    let (init_tx, init_rx) = oneshot::channel::<Box<dyn OpenModule + Send>>();
     task::spawn(async move {
         let pusher = AdaPusher::new().await;
         let _ = init_tx.send(Box::new(pusher));
@@ -57,140 +109,194 @@ fn spawn_ada_pusher_init() -> tokio::sync::oneshot::Receiver<Box<dyn OpenModule
 }
 
 #[cfg(not(feature = "ada_pusher"))]
-fn spawn_dummy_init() -> tokio::sync::oneshot::Receiver<Box<dyn OpenModule + Send>> {
-    let (init_tx, init_rx) = tokio::sync::oneshot::channel::<Box<dyn OpenModule + Send>>();
+fn spawn_module_init() -> oneshot::Receiver<Box<dyn OpenModule + Send>> {
+    let (init_tx, init_rx) = oneshot::channel::<Box<dyn OpenModule + Send>>();
     task::spawn(async move {
         let _ = init_tx.send(Box::new(Dummy {}));
     });
     init_rx
 }
 
-impl DoorOpener {
-    #[must_use]
-    #[cfg(feature = "ada_pusher")]
-    pub fn new(auth_tx: UnboundedSender<AuthState>) -> DoorOpener {
-        let (tx, mut rx) = unbounded_channel::<()>();
-
-        task::spawn(async move {
-            let mut module: Option<Box<dyn OpenModule + Send>> = None;
-            let init_rx = spawn_ada_pusher_init();
+/// Resolves once the pending module initialization finishes, or never if none is pending
+async fn wait_for_init(
+    init_rx: &mut Option<oneshot::Receiver<Box<dyn OpenModule + Send>>>,
+) -> Option<Box<dyn OpenModule + Send>> {
+    match init_rx {
+        Some(irx) => irx.await.ok(),
+        None => future::pending().await,
+    }
+}
 
-            let mut init_rx = Some(init_rx);
+/// Re-actuation timer for an open house with an interval
+fn reactuation_timer(open_house: Option<OpenHouse>) -> Option<Interval> {
+    let period = Duration::from_secs(open_house?.interval_secs?);
+    // The first tick completes immediately, opening the door as soon as open house starts
+    let mut timer = time::interval(period);
+    timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    Some(timer)
+}
 
-            loop {
-                {
-                    if let Some(ref mut irx) = init_rx {
-                        tokio::select! {
-                            result = irx => {
-                                if let Ok(m) = result {
-                                    module = Some(m);
-                                    println!("Door module initialized successfully!");
-                                }
-                                init_rx = None;
-                            }
-                            msg = rx.recv() => {
-                                match msg {
-                                    Some(()) => {
-                                        if let Some(ref mut m) = module {
-                                            if !open_with_retry(m.as_mut()).await {
-                                                module = None;
-                                                init_rx = Some(spawn_ada_pusher_init());
-                                            }
-                                        } else {
-                                            let _ = auth_tx.send(AuthState::DoorHWNotReady);
-                                        }
-                                    }
-                                    None => return,
-                                }
-                            }
-                        }
-                        continue;
-                    }
-                }
+/// Resolves on the next re-actuation, or never if open house is not re-actuating
+async fn wait_for_reactuation(timer: &mut Option<Interval>) {
+    match timer {
+        Some(timer) => {
+            timer.tick().await;
+        }
+        None => future::pending().await,
+    }
+}
 
-                match rx.recv().await {
-                    Some(()) => {
-                        if let Some(ref mut m) = module {
-                            if !open_with_retry(m.as_mut()).await {
-                                module = None;
-                                {
-                                    init_rx = Some(spawn_ada_pusher_init());
-                                }
-                            }
-                        } else {
-                            let _ = auth_tx.send(AuthState::DoorHWNotReady);
-                        }
-                    }
-                    None => return,
-                }
-            }
+/// Opens the door for a request, or reports that the module is not ready
+///
+/// Requests refused by their source's policy, such as during lockdown, actuate
+/// nothing. A module failing every retry is dropped and initialized again.
+async fn actuate(
+    module: &mut Option<Box<dyn OpenModule + Send>>,
+    init_rx: &mut Option<oneshot::Receiver<Box<dyn OpenModule + Send>>>,
+    request: OpenRequest,
+) {
+    let source = request.source.name();
+    if let Some(refusal) = request.refusal() {
+        warn!(
+            source,
+            actor = request.actor.as_deref(),
+            reason = %request.reason,
+            refusal,
+            "refusing to open the door"
+        );
+        metrics::record_open_request(source, refusal);
+        audit::record(&AuditEvent::OpenRefused {
+            reason: refusal.into(),
+            source: source.into(),
         });
-        Self { tx }
+        if refusal == "lockdown" {
+            events::publish(Event::Auth(AuthState::Lockdown));
+        }
+        return;
     }
 
-    #[must_use]
-    #[cfg(not(feature = "ada_pusher"))]
-    pub fn new(auth_tx: UnboundedSender<AuthState>) -> DoorOpener {
-        let (tx, mut rx) = unbounded_channel::<()>();
+    info!(
+        source,
+        actor = request.actor.as_deref(),
+        reason = %request.reason,
+        "opening the door"
+    );
+    metrics::record_open_request(source, "accepted");
+    if let Some(m) = module {
+        if !open_with_retry(m.as_mut(), request.source).await {
+            *module = None;
+            health::set_status(Subsystem::Door, module_not_ready());
+            events::publish(Event::Connectivity {
+                link: Link::DoorModule,
+                connected: false,
+            });
+            *init_rx = Some(spawn_module_init());
+        }
+    } else {
+        metrics::record_door_not_ready();
+        events::publish(Event::Auth(AuthState::DoorHWNotReady));
+    }
+}
+
+fn module_not_ready() -> Status {
+    Status::Degraded(String::from("door module not connected"))
+}
 
-        task::spawn(async move {
-            let mut module: Option<Box<dyn OpenModule + Send>> = None;
-            let init_rx = spawn_dummy_init();
+/// Actuation loop, owning the door module and handling open requests in order
+///
+/// While an open house with an interval is running, the door is also
+/// re-actuated on that interval.
+///
+/// On shutdown, an actuation in progress is allowed to finish before the module
+/// is disconnected.
+async fn door_task(rx: Arc<Mutex<UnboundedReceiver<OpenRequest>>>) {
+    // Held for the lifetime of this run; released if the task panics so a restart can take over
+    let mut rx = rx.lock().await;
 
-            let mut init_rx = Some(init_rx);
+    let mut module: Option<Box<dyn OpenModule + Send>> = None;
+    let mut init_rx = Some(spawn_module_init());
+    let mut heartbeat = time::interval(HEARTBEAT_INTERVAL);
+    let mut mode_rx = mode::subscribe_open_house();
+    let mut reactuation = reactuation_timer(*mode_rx.borrow_and_update());
+    let mut presence = motion::subscribe();
 
-            loop {
+    loop {
+        tokio::select! {
+            result = wait_for_init(&mut init_rx) => {
+                if let Some(m) = result {
+                    module = Some(m);
+                    health::set_status(Subsystem::Door, Status::Ready);
+                    events::publish(Event::Connectivity {
+                        link: Link::DoorModule,
+                        connected: true,
+                    });
+                    println!("Door module initialized successfully!");
+                }
+                init_rx = None;
+            }
+            _ = heartbeat.tick() => {
+                health::beat(Subsystem::Door);
+            }
+            () = shutdown::wait() => break,
+            Ok(event) = presence.recv() => {
+                if event.kind == PresenceKind::Arrived
+                    && let Some(m) = &mut module
                 {
-                    if let Some(ref mut irx) = init_rx {
-                        tokio::select! {
-                            result = irx => {
-                                if let Ok(m) = result {
-                                    module = Some(m);
-                                    println!("Dummy door module initialized successfully!");
-                                }
-                                init_rx = None;
-                            }
-                            msg = rx.recv() => {
-                                match msg {
-                                    Some(()) => {
-                                        if let Some(ref mut m) = module {
-                                            if !open_with_retry(m.as_mut()).await {
-                                                module = None;
-                                                init_rx = Some(spawn_dummy_init());
-                                            }
-                                        } else {
-                                            let _ = auth_tx.send(AuthState::DoorHWNotReady);
-                                        }
-                                    }
-                                    None => return,
-                                }
-                            }
-                        }
-                        continue;
+                    match time::timeout(PREPARE_TIMEOUT, m.prepare()).await {
+                        Ok(Ok(())) => {}
+                        Ok(Err(e)) => warn!(error = %e, "failed to prepare door module"),
+                        Err(_) => warn!(
+                            timeout_secs = PREPARE_TIMEOUT.as_secs(),
+                            "timed out preparing door module"
+                        ),
                     }
                 }
-
-                match rx.recv().await {
-                    Some(()) => {
-                        if let Some(ref mut m) = module {
-                            if !open_with_retry(m.as_mut()).await {
-                                module = None;
-                                {
-                                    init_rx = Some(spawn_dummy_init());
-                                }
-                            }
-                        } else {
-                            let _ = auth_tx.send(AuthState::DoorHWNotReady);
-                        }
-                    }
-                    None => return,
+            }
+            Ok(()) = mode_rx.changed() => {
+                reactuation = reactuation_timer(*mode_rx.borrow_and_update());
+            }
+            () = wait_for_reactuation(&mut reactuation) => {
+                // Checking again clears an open house which has just expired
+                if mode::open_house().is_some() {
+                    let request = OpenRequest::new(OpenSource::OpenHouse, None, "interval");
+                    actuate(&mut module, &mut init_rx, request).await;
                 }
             }
-        });
-        Self { tx }
+            msg = rx.recv() => {
+                match msg {
+                    Some(request) => actuate(&mut module, &mut init_rx, request).await,
+                    None => break,
+                }
+            }
+        }
+    }
+
+    if let Some(mut m) = module {
+        match m.disconnect().await {
+            Ok(()) => info!("door module disconnected"),
+            Err(e) => warn!(error = %e, "failed to disconnect door module"),
+        }
+    }
+}
+
+impl DoorOpener {
+    /// Starts the supervised door task
+    #[must_use]
+    pub fn spawn() -> DoorOpener {
+        let (tx, rx) = unbounded_channel::<OpenRequest>();
+        let rx = Arc::new(Mutex::new(rx));
+
+        let task = supervise(Subsystem::Door, move || task::spawn(door_task(rx.clone())));
+        Self { tx, task }
+    }
+
+    /// Waits for the door task to finish after shutdown has been triggered
+    pub async fn join(self) {
+        let _ = self.task.await;
     }
 
-    pub fn open(&self) {
-        let _ = self.tx.send(());
+    /// Queues a request to open the door, which is checked against its source's policy
+    pub fn open(&self, request: OpenRequest) {
+        let _ = self.tx.send(request);
     }
 }
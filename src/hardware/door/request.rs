@@ -0,0 +1,113 @@
+//! Open requests, and whether each may open the door
+//!
+//! Every request says where it came from, who made it if known and why, so
+//! refusals and actuations can be told apart in logs, metrics and the audit log.
+//!
+//! Sources can be turned off with `DISABLED_OPEN_SOURCES`, a comma separated
+//! list of source names such as `websocket,guest_pass`.
+
+use std::env;
+use std::sync::LazyLock;
+
+use tracing::warn;
+
+use crate::mode;
+
+static DISABLED: LazyLock<Vec<OpenSource>> = LazyLock::new(|| {
+    let Ok(sources) = env::var("DISABLED_OPEN_SOURCES") else {
+        return Vec::new();
+    };
+    sources
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .filter_map(|name| {
+            let source = OpenSource::parse(name);
+            if source.is_none() {
+                warn!(
+                    value = name,
+                    "unknown open source in DISABLED_OPEN_SOURCES, ignoring"
+                );
+            }
+            source
+        })
+        .collect()
+});
+
+/// Where an open request came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenSource {
+    /// A passport tapped and validated at the reader
+    Tap,
+    /// An `Open` message over the websocket, such as from the phone bell
+    Websocket,
+    /// A guest pass redeemed over the websocket or admin API
+    GuestPass,
+    /// Open house re-actuating the door on its interval
+    OpenHouse,
+    /// The Space key, which only exists in dev builds
+    #[cfg(debug_assertions)]
+    Debug,
+}
+
+impl OpenSource {
+    /// Name of the source, as used in logs, metrics and the audit log
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Tap => "tap",
+            Self::Websocket => "websocket",
+            Self::GuestPass => "guest_pass",
+            Self::OpenHouse => "open_house",
+            #[cfg(debug_assertions)]
+            Self::Debug => "debug",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "tap" => Self::Tap,
+            "websocket" => Self::Websocket,
+            "guest_pass" => Self::GuestPass,
+            "open_house" => Self::OpenHouse,
+            #[cfg(debug_assertions)]
+            "debug" => Self::Debug,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OpenRequest {
+    pub source: OpenSource,
+    /// Who asked, such as `passport:42` or a guest pass label
+    pub actor: Option<String>,
+    /// Why the door should open, such as the scan result
+    pub reason: String,
+}
+
+impl OpenRequest {
+    #[must_use]
+    pub fn new(source: OpenSource, actor: Option<String>, reason: impl Into<String>) -> Self {
+        Self {
+            source,
+            actor,
+            reason: reason.into(),
+        }
+    }
+
+    /// Why the request may not open the door right now, if it may not
+    #[must_use]
+    pub fn refusal(&self) -> Option<&'static str> {
+        // Nothing opens the door during lockdown, remote or not. Taps are turned
+        // away by auth already, but lockdown may have started since the passport
+        // was validated
+        if mode::is_locked_down() {
+            Some("lockdown")
+        } else if DISABLED.contains(&self.source) {
+            Some("source_disabled")
+        } else {
+            None
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use std::fmt::Write;
 use std::time::Duration;
 
 use nfc1::{Context, Device, Error, Target, target_info::TargetInfo};
@@ -5,10 +6,38 @@ use nfc1::{Context, Device, Error, Target, target_info::TargetInfo};
 pub mod parser;
 pub mod structs;
 
-use crate::hardware::nfc::{parser::parse_nfc_data, structs::PassportData};
+use crate::hardware::nfc::{
+    parser::{ndef_length, parse_nfc_data},
+    structs::PassportData,
+};
+
+/// First page of user memory on NTAG21x tags
+const FIRST_USER_PAGE: u8 = 4;
+/// Pages before this are always read
+const MIN_READ_END: u8 = 50;
+/// Last page of user memory on the largest NTAG21x tags
+const LAST_USER_PAGE: u8 = 225;
+/// Polls made per call to `poll`, so it returns regularly when nothing is tapped
+const POLL_COUNT: u8 = 2;
+const POLL_PERIOD: Duration = Duration::from_millis(150);
+
+/// Hex encoded UID of a polled tag, if it reports one
+#[must_use]
+pub fn tag_uid(target: &Target) -> Option<String> {
+    let TargetInfo::Iso14443a(target_info) = &target.target_info else {
+        return None;
+    };
+    let uid = target_info.uid.get(..target_info.uid_len)?;
+    Some(uid.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    }))
+}
 
 pub struct NFCReader {
+    // Declared before `_context` so the device is closed before its context is freed
     device: Device,
+    _context: Box<Context>,
 }
 
 impl NFCReader {
@@ -23,30 +52,38 @@ impl NFCReader {
     ///
     /// Will panic if NFC device is not found or cannot be initialized
     pub fn new() -> Result<NFCReader, Error> {
-        let context: &'static mut Context = Box::leak(Box::new(Context::new().unwrap()));
+        let mut context = Box::new(Context::new().unwrap());
         let mut device: Device = context.open()?;
 
         device.initiator_init()?;
         device.set_property_bool(nfc1::Property::InfiniteSelect, true)?;
         device.set_property_bool(nfc1::Property::AutoIso144434, true)?;
 
-        Ok(Self { device })
+        Ok(Self {
+            device,
+            _context: context,
+        })
     }
 
-    /// Polls NFC reader
+    /// Polls NFC reader for a moment, returning `None` if nothing was tapped
     ///
     /// # Errors
     ///
     /// Will error if polling NFC device fails
-    pub fn poll(&mut self) -> Result<Target, Error> {
-        self.device.initiator_poll_target(
+    pub fn poll(&mut self) -> Result<Option<Target>, Error> {
+        match self.device.initiator_poll_target(
             &[nfc1::Modulation {
                 modulation_type: nfc1::ModulationType::Iso14443a,
                 baud_rate: nfc1::BaudRate::Baud106,
             }],
-            0xff,
-            Duration::from_millis(150),
-        )
+            POLL_COUNT,
+            POLL_PERIOD,
+        ) {
+            // Polls which find nothing either time out or return an empty target
+            Ok(target) if tag_uid(&target).is_some() => Ok(Some(target)),
+            Ok(_) | Err(Error::Timeout) => Ok(None),
+            Err(e) => Err(e),
+        }
     }
 
     /// Read from NFC reader
@@ -62,11 +99,18 @@ impl NFCReader {
 
             let mut passport_data: Vec<u8> = vec![];
 
-            for n in (4u8..50).step_by(4) {
-                match self
-                    .device
-                    .initiator_transceive_bytes(&[0x30, n], 16, nfc1::Timeout::Default)
-                {
+            // Passports without a credential fit in the first pages, longer
+            // messages are read until they end
+            let mut page = FIRST_USER_PAGE;
+            while page < MIN_READ_END
+                || (page <= LAST_USER_PAGE
+                    && ndef_length(&passport_data).is_none_or(|len| passport_data.len() < len))
+            {
+                match self.device.initiator_transceive_bytes(
+                    &[0x30, page],
+                    16,
+                    nfc1::Timeout::Default,
+                ) {
                     Ok(data) => {
                         for byte in data {
                             passport_data.push(byte);
@@ -76,11 +120,17 @@ impl NFCReader {
                         return Err(e);
                     }
                 }
+                page += 4;
+            }
+
+            if ndef_length(&passport_data).is_none_or(|len| passport_data.len() < len) {
+                return Err(Error::OperationAborted);
             }
 
             let message = parse_nfc_data(&passport_data);
 
-            if message.records.len() != 3 {
+            // The fourth record, if present, is a signed credential
+            if !(3..=4).contains(&message.records.len()) {
                 return Err(Error::OperationAborted);
             }
 
@@ -92,6 +142,7 @@ impl NFCReader {
             Ok(PassportData {
                 id: passport_id,
                 secret: passport_secret,
+                credential: message.records.get(3).map(|record| record.data.clone()),
             })
         } else {
             Err(Error::DeviceNotSupported)
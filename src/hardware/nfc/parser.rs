@@ -137,6 +137,18 @@ fn read_ndef_header(data: &[u8]) -> (u8, usize, usize) {
     }
 }
 
+/// Number of bytes the NDEF message at the start of `data` takes up, header included
+///
+/// Returns `None` if too little of the message has been read to tell.
+#[must_use]
+pub fn ndef_length(data: &[u8]) -> Option<usize> {
+    if data.len() < 4 {
+        return None;
+    }
+    let (_, message_length, data_offset) = read_ndef_header(data);
+    Some(message_length + data_offset)
+}
+
 /// Parses NFC data into NDEF structure
 #[must_use]
 pub fn parse_nfc_data(data: &[u8]) -> ParseResult {
@@ -1,4 +1,6 @@
 pub struct PassportData {
     pub(crate) id: i32,
     pub(crate) secret: std::string::String,
+    /// Signed credential, verified without the ID server
+    pub(crate) credential: Option<std::string::String>,
 }
@@ -0,0 +1,132 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long a subsystem may go without a heartbeat before it is considered stalled
+pub const LIVENESS_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Long-running parts of the app that report their health
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Subsystem {
+    Gui,
+    Reader,
+    Door,
+    Websocket,
+    Camera,
+}
+
+impl fmt::Display for Subsystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Subsystem::Gui => "gui",
+            Subsystem::Reader => "reader",
+            Subsystem::Door => "door",
+            Subsystem::Websocket => "websocket",
+            Subsystem::Camera => "camera",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    /// Has not finished initializing yet
+    Starting,
+    Ready,
+    /// Running, but not fully functional
+    Degraded(String),
+    /// Compiled out or intentionally not running; excluded from liveness checks
+    Disabled,
+}
+
+#[derive(Debug, Clone)]
+pub struct SubsystemHealth {
+    pub subsystem: Subsystem,
+    pub status: Status,
+    pub last_beat: Option<Instant>,
+    /// Times the subsystem's task has been restarted by the supervisor
+    pub restarts: u32,
+}
+
+impl SubsystemHealth {
+    /// Whether the subsystem has reported a heartbeat recently enough
+    #[must_use]
+    pub fn is_alive(&self) -> bool {
+        self.status == Status::Disabled
+            || self
+                .last_beat
+                .is_some_and(|beat| beat.elapsed() < LIVENESS_TIMEOUT)
+    }
+
+    /// Whether the subsystem has finished initializing
+    #[must_use]
+    pub fn is_up(&self) -> bool {
+        matches!(self.status, Status::Ready | Status::Disabled)
+    }
+}
+
+static REGISTRY: LazyLock<Mutex<BTreeMap<Subsystem, SubsystemHealth>>> = LazyLock::new(|| {
+    Mutex::new(
+        [
+            Subsystem::Gui,
+            Subsystem::Reader,
+            Subsystem::Door,
+            Subsystem::Websocket,
+            Subsystem::Camera,
+        ]
+        .into_iter()
+        .map(|subsystem| {
+            (
+                subsystem,
+                SubsystemHealth {
+                    subsystem,
+                    status: Status::Starting,
+                    last_beat: None,
+                    restarts: 0,
+                },
+            )
+        })
+        .collect(),
+    )
+});
+
+fn update(subsystem: Subsystem, f: impl FnOnce(&mut SubsystemHealth)) {
+    let mut registry = REGISTRY
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    if let Some(entry) = registry.get_mut(&subsystem) {
+        f(entry);
+    }
+}
+
+/// Records that a subsystem's task is still making progress
+pub fn beat(subsystem: Subsystem) {
+    update(subsystem, |entry| entry.last_beat = Some(Instant::now()));
+}
+
+/// Updates a subsystem's status, which also counts as a heartbeat
+pub fn set_status(subsystem: Subsystem, status: Status) {
+    update(subsystem, |entry| {
+        entry.status = status;
+        entry.last_beat = Some(Instant::now());
+    });
+}
+
+/// Records that a subsystem's task died and is being restarted
+pub fn record_restart(subsystem: Subsystem, reason: String) {
+    update(subsystem, |entry| {
+        entry.status = Status::Degraded(reason);
+        entry.restarts += 1;
+    });
+}
+
+/// Returns the current health of every subsystem
+#[must_use]
+pub fn snapshot() -> Vec<SubsystemHealth> {
+    REGISTRY
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+        .values()
+        .cloned()
+        .collect()
+}
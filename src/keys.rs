@@ -0,0 +1,56 @@
+//! Ed25519 public keys trusted to sign data pushed to the door
+
+use std::env;
+use std::error::Error;
+
+use base64::Engine;
+use base64::engine::general_purpose;
+use ed25519_dalek::{Signature, VerifyingKey};
+
+type Result<T> = std::result::Result<T, Box<dyn Error + Send + Sync>>;
+
+/// Loads the comma separated, base64 encoded keys in the environment variable `var`
+///
+/// Listing several keys lets a new signing key be rolled out before the old one
+/// is retired. An unset variable trusts no keys.
+///
+/// # Errors
+///
+/// Will error if any of the keys is not a valid Ed25519 public key
+pub fn from_env(var: &str) -> Result<Vec<VerifyingKey>> {
+    let Ok(keys) = env::var(var) else {
+        return Ok(Vec::new());
+    };
+    keys.split(',')
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+        .map(|key| {
+            let bytes: [u8; 32] = general_purpose::STANDARD
+                .decode(key)?
+                .try_into()
+                .map_err(|_| format!("{var}: keys must be 32 bytes"))?;
+            Ok(VerifyingKey::from_bytes(&bytes)?)
+        })
+        .collect()
+}
+
+/// Checks that one of `keys` made `signature` over `message`
+///
+/// # Errors
+///
+/// Will error if no key is trusted, the signature is malformed, or no trusted
+/// key made it
+pub fn verify(keys: &[VerifyingKey], message: &[u8], signature: &[u8]) -> Result<()> {
+    if keys.is_empty() {
+        return Err("no signing keys are trusted".into());
+    }
+    let signature = Signature::from_slice(signature)?;
+    if keys
+        .iter()
+        .any(|key| key.verify_strict(message, &signature).is_ok())
+    {
+        Ok(())
+    } else {
+        Err("signature does not match any trusted key".into())
+    }
+}
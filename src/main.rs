@@ -1,27 +1,55 @@
+mod admin;
+pub mod alerts;
+mod audio;
+pub mod audit;
 pub mod auth;
 mod camera;
 pub mod config;
 pub mod enums;
+pub mod events;
+pub mod guest;
 pub mod gui;
 pub mod hardware;
+pub mod health;
+mod http;
+mod keys;
+pub mod metrics;
+pub mod mode;
+mod persist;
+pub mod policy;
+pub mod revocation;
+pub mod shutdown;
+pub mod status;
+mod supervisor;
+mod systemd;
 pub mod timedvariable;
 #[cfg(not(debug_assertions))]
 mod updater;
 pub mod websocket;
 
+use std::time::Duration;
+
 use auth::auth_entry;
-use tokio::{
-    sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel},
-    task,
-};
-use tracing::info;
+use futures::future::join_all;
+use tokio::{sync::mpsc::UnboundedReceiver, task, time};
+use tracing::{info, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use crate::{enums::AuthState, gui::gui_entry, hardware::door::DoorOpener, websocket::ws_entry};
+use crate::{
+    gui::gui_entry,
+    hardware::door::{DoorOpener, OpenRequest},
+    health::Subsystem,
+    supervisor::supervise,
+    websocket::ws_entry,
+};
 
 #[cfg(not(debug_assertions))]
 use updater::update_check;
 
+/// How long subsystems get to wind down before the runtime is torn down
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(15);
+const SENTRY_FLUSH_TIMEOUT: Duration = Duration::from_secs(2);
+
 #[dotenvy::load(path = ".env", required = true, override_ = false)]
 fn main() {
     let sentry_options = sentry::ClientOptions::new()
@@ -35,44 +63,78 @@ fn main() {
         .with(sentry::integrations::tracing::layer())
         .init();
 
-    tokio::runtime::Builder::new_multi_thread()
+    let runtime = tokio::runtime::Builder::new_multi_thread()
         .enable_all()
         .build()
-        .unwrap()
-        .block_on(async {
-            #[cfg(not(debug_assertions))]
-            if update_check().await {
-                info!("finished updating to a newer version, closing");
-                // Quit, systemd will pick us back up
-                return;
-            }
-
-            let (auth_tx, gui_rx) = unbounded_channel::<AuthState>();
-            let (opener_tx, opener_rx) = unbounded_channel::<()>();
-            let auth_opener = opener_tx.clone();
-            let gui_opener = opener_tx.clone();
-            let door_auth_tx = auth_tx.clone();
-
-            task::spawn_blocking(move || {
-                auth_entry(&auth_tx, &auth_opener);
-            });
-
-            task::spawn(ws_entry(move || {
-                let _ = opener_tx.send(());
-            }));
-
-            task::spawn(opener_entry(opener_rx, door_auth_tx));
-
-            gui_entry(gui_rx, gui_opener);
+        .unwrap();
+
+    runtime.block_on(async {
+        status::mark_started();
+        shutdown::spawn_signal_handler();
+        metrics::spawn_server();
+        systemd::spawn_watchdog();
+
+        #[cfg(not(debug_assertions))]
+        if update_check().await {
+            info!("finished updating to a newer version, closing");
+            // Quit, systemd will pick us back up
+            return;
+        }
+
+        // Subscribed before anything publishes, so no event is missed
+        let gui_events = events::subscribe();
+        audio::spawn_player();
+        task::spawn(status::track_activity(events::subscribe()));
+        admin::spawn_server();
+        guest::spawn_expiry();
+
+        let reader = supervise(Subsystem::Reader, || task::spawn_blocking(auth_entry));
+
+        let websocket = supervise(Subsystem::Websocket, || {
+            task::spawn(ws_entry(events::request_open))
         });
+
+        let camera = supervise(Subsystem::Camera, || {
+            task::spawn_blocking(camera::camera_entry)
+        });
+
+        let opener = task::spawn(opener_entry(
+            events::take_open_requests().expect("open requests are only taken once"),
+        ));
+
+        // Returns once Escape is pressed or a signal triggers shutdown
+        gui_entry(gui_events);
+
+        shutdown::trigger();
+        info!("shutting down");
+        if time::timeout(
+            SHUTDOWN_TIMEOUT,
+            join_all([reader, websocket, camera, opener]),
+        )
+        .await
+        .is_err()
+        {
+            warn!("subsystems did not stop in time, exiting anyway");
+        }
+    });
+
+    audit::flush();
+    if let Some(client) = sentry::Hub::current().client() {
+        client.flush(Some(SENTRY_FLUSH_TIMEOUT));
+    }
+
+    // Subsystems have stopped by now, unless they missed SHUTDOWN_TIMEOUT above,
+    // in which case they are not waited on a second time
+    runtime.shutdown_timeout(Duration::from_secs(1));
 }
 
-async fn opener_entry(mut opener_rx: UnboundedReceiver<()>, auth_tx: UnboundedSender<AuthState>) {
-    let door_opener = DoorOpener::new(auth_tx);
+async fn opener_entry(mut requests: UnboundedReceiver<OpenRequest>) {
+    let door_opener = DoorOpener::spawn();
     loop {
-        if opener_rx.recv().await.is_some() {
-            info!("opener_entry received open message");
-            door_opener.open();
+        tokio::select! {
+            Some(request) = requests.recv() => door_opener.open(request),
+            () = shutdown::wait() => break,
         }
     }
+    door_opener.join().await;
 }
@@ -0,0 +1,31 @@
+use std::time::Duration;
+
+pub fn spawn_server() {}
+
+pub fn record_scan(_result: &str) {}
+
+pub fn record_id_api_request(_outcome: &str, _elapsed: Duration) {}
+
+pub fn record_open_request(_source: &str, _outcome: &str) {}
+
+pub fn record_door_open_attempt(_success: bool) {}
+
+pub fn record_door_open(_success: bool, _elapsed: Duration) {}
+
+pub fn record_door_not_ready() {}
+
+pub fn record_door_module_reinit() {}
+
+pub fn record_ble_connect_failure() {}
+
+pub fn record_websocket_connected() {}
+
+pub fn record_websocket_disconnected() {}
+
+pub fn record_websocket_connect_failure() {}
+
+pub fn record_photo_capture(_success: bool, _elapsed: Duration) {}
+
+pub fn record_update_check(_result: &str) {}
+
+pub fn record_subsystem_restart(_subsystem: &str) {}
@@ -0,0 +1,253 @@
+use std::env;
+use std::sync::LazyLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use prometheus::{
+    Encoder, Gauge, HistogramVec, IntCounter, IntCounterVec, IntGauge, TextEncoder, register_gauge,
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge,
+};
+use tracing::error;
+
+use super::DEFAULT_LISTEN_ADDR;
+use crate::http::{self, Request, Response};
+
+const CAMERA_BUCKETS: &[f64] = &[0.25, 0.5, 1.0, 2.0, 4.0, 8.0, 16.0, 32.0];
+
+static SCANS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!("door_scans_total", "Passport scans by result", &["result"])
+        .expect("register door_scans_total")
+});
+
+static ID_API_REQUESTS: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "door_id_api_request_duration_seconds",
+        "Latency of passport validity checks against the ID server",
+        &["outcome"]
+    )
+    .expect("register door_id_api_request_duration_seconds")
+});
+
+static DOOR_OPEN_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "door_open_requests_total",
+        "Open requests by source, and whether they were accepted or why they were refused",
+        &["source", "outcome"]
+    )
+    .expect("register door_open_requests_total")
+});
+
+static DOOR_OPEN_ATTEMPTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "door_open_attempts_total",
+        "Individual actuation attempts made by the door module",
+        &["result"]
+    )
+    .expect("register door_open_attempts_total")
+});
+
+static DOOR_OPENS: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "door_open_duration_seconds",
+        "Time taken to open the door, including retries",
+        &["result"]
+    )
+    .expect("register door_open_duration_seconds")
+});
+
+static DOOR_LAST_SUCCESS: LazyLock<Gauge> = LazyLock::new(|| {
+    register_gauge!(
+        "door_last_successful_open_timestamp_seconds",
+        "Unix time of the last successful door actuation"
+    )
+    .expect("register door_last_successful_open_timestamp_seconds")
+});
+
+static DOOR_NOT_READY: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "door_not_ready_total",
+        "Open requests dropped because the door module was not initialized"
+    )
+    .expect("register door_not_ready_total")
+});
+
+static DOOR_MODULE_REINITS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "door_module_reinitializations_total",
+        "Times the door module was torn down and reconnected after failing"
+    )
+    .expect("register door_module_reinitializations_total")
+});
+
+static BLE_CONNECT_FAILURES: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "ble_connect_failures_total",
+        "Failed attempts to find and connect to ada-pusher"
+    )
+    .expect("register ble_connect_failures_total")
+});
+
+static WEBSOCKET_CONNECTED: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "websocket_connected",
+        "Whether the API websocket is currently connected"
+    )
+    .expect("register websocket_connected")
+});
+
+static WEBSOCKET_CONNECTED_SINCE: LazyLock<Gauge> = LazyLock::new(|| {
+    register_gauge!(
+        "websocket_connected_since_timestamp_seconds",
+        "Unix time at which the current websocket connection was established"
+    )
+    .expect("register websocket_connected_since_timestamp_seconds")
+});
+
+static WEBSOCKET_CONNECTIONS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "websocket_connections_total",
+        "Successful websocket connections, including reconnects"
+    )
+    .expect("register websocket_connections_total")
+});
+
+static WEBSOCKET_CONNECT_FAILURES: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "websocket_connect_failures_total",
+        "Failed attempts to connect to the API websocket"
+    )
+    .expect("register websocket_connect_failures_total")
+});
+
+static PHOTO_CAPTURES: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "camera_capture_duration_seconds",
+        "Time taken to capture and encode a photo",
+        &["result"],
+        CAMERA_BUCKETS.to_vec()
+    )
+    .expect("register camera_capture_duration_seconds")
+});
+
+static UPDATE_CHECKS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "updater_checks_total",
+        "Update checks by result",
+        &["result"]
+    )
+    .expect("register updater_checks_total")
+});
+
+static SUBSYSTEM_RESTARTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "subsystem_restarts_total",
+        "Subsystem tasks restarted by the supervisor after dying",
+        &["subsystem"]
+    )
+    .expect("register subsystem_restarts_total")
+});
+
+fn result_label(success: bool) -> &'static str {
+    if success { "success" } else { "failure" }
+}
+
+fn unix_now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+/// Spawns the `/metrics` HTTP server
+///
+/// Listens on `METRICS_LISTEN_ADDR`, or [`DEFAULT_LISTEN_ADDR`] if unset.
+pub fn spawn_server() {
+    let addr = env::var("METRICS_LISTEN_ADDR").unwrap_or_else(|_| DEFAULT_LISTEN_ADDR.into());
+    http::spawn_server("metrics", addr, |request| async move {
+        handle_request(&request)
+    });
+}
+
+fn handle_request(request: &Request) -> Response {
+    if request.method != "GET" || request.path != "/metrics" {
+        return Response::empty(404);
+    }
+
+    let mut body = Vec::new();
+    let encoder = TextEncoder::new();
+    if let Err(e) = encoder.encode(&prometheus::gather(), &mut body) {
+        error!(error = %e, "failed to encode metrics");
+        return Response::empty(500);
+    }
+    Response::new(200, encoder.format_type(), body)
+}
+
+pub fn record_scan(result: &str) {
+    SCANS.with_label_values(&[result]).inc();
+}
+
+pub fn record_id_api_request(outcome: &str, elapsed: Duration) {
+    ID_API_REQUESTS
+        .with_label_values(&[outcome])
+        .observe(elapsed.as_secs_f64());
+}
+
+pub fn record_open_request(source: &str, outcome: &str) {
+    DOOR_OPEN_REQUESTS
+        .with_label_values(&[source, outcome])
+        .inc();
+}
+
+pub fn record_door_open_attempt(success: bool) {
+    DOOR_OPEN_ATTEMPTS
+        .with_label_values(&[result_label(success)])
+        .inc();
+}
+
+pub fn record_door_open(success: bool, elapsed: Duration) {
+    DOOR_OPENS
+        .with_label_values(&[result_label(success)])
+        .observe(elapsed.as_secs_f64());
+    if success {
+        DOOR_LAST_SUCCESS.set(unix_now());
+    }
+}
+
+pub fn record_door_not_ready() {
+    DOOR_NOT_READY.inc();
+}
+
+pub fn record_door_module_reinit() {
+    DOOR_MODULE_REINITS.inc();
+}
+
+pub fn record_ble_connect_failure() {
+    BLE_CONNECT_FAILURES.inc();
+}
+
+pub fn record_websocket_connected() {
+    WEBSOCKET_CONNECTIONS.inc();
+    WEBSOCKET_CONNECTED.set(1);
+    WEBSOCKET_CONNECTED_SINCE.set(unix_now());
+}
+
+pub fn record_websocket_disconnected() {
+    WEBSOCKET_CONNECTED.set(0);
+}
+
+pub fn record_websocket_connect_failure() {
+    WEBSOCKET_CONNECT_FAILURES.inc();
+}
+
+pub fn record_photo_capture(success: bool, elapsed: Duration) {
+    PHOTO_CAPTURES
+        .with_label_values(&[result_label(success)])
+        .observe(elapsed.as_secs_f64());
+}
+
+pub fn record_update_check(result: &str) {
+    UPDATE_CHECKS.with_label_values(&[result]).inc();
+}
+
+pub fn record_subsystem_restart(subsystem: &str) {
+    SUBSYSTEM_RESTARTS.with_label_values(&[subsystem]).inc();
+}
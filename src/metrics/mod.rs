@@ -0,0 +1,18 @@
+//! Prometheus metrics
+//!
+//! With the `metrics` feature enabled, counters and histograms are recorded and
+//! exposed on a `/metrics` HTTP endpoint. Without it, every recording function
+//! is a no-op so call sites do not need to be feature gated.
+
+#[cfg(not(feature = "metrics"))]
+mod dummy;
+#[cfg(feature = "metrics")]
+mod exporter;
+
+#[cfg(not(feature = "metrics"))]
+pub use self::dummy::*;
+#[cfg(feature = "metrics")]
+pub use self::exporter::*;
+
+/// Address the metrics endpoint listens on when `METRICS_LISTEN_ADDR` is unset
+pub const DEFAULT_LISTEN_ADDR: &str = "0.0.0.0:9464";
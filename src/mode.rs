@@ -0,0 +1,264 @@
+//! Door modes which override the usual passport checks
+//!
+//! Open house mode lets anyone in until a deadline, either by re-actuating the
+//! door on an interval or by opening for any tapped tag. It always expires, so
+//! it can never be left on by accident.
+//!
+//! Lockdown stops the door from opening at all. It is saved to
+//! `LOCKDOWN_STATE_PATH` so it survives restarts, and lasts until it is lifted.
+
+use std::path::PathBuf;
+use std::sync::LazyLock;
+use std::time::Duration;
+use std::{env, fs, io};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+
+use crate::audit::{self, AuditEvent};
+use crate::persist;
+
+/// Longest open house allowed when `OPEN_HOUSE_MAX_SECS` is unset
+pub const DEFAULT_OPEN_HOUSE_MAX: Duration = Duration::from_secs(6 * 60 * 60);
+/// Lockdown state location used when `LOCKDOWN_STATE_PATH` is unset
+pub const DEFAULT_LOCKDOWN_STATE_PATH: &str = "lockdown.json";
+/// Re-actuating more often than this would wear out the door module
+pub const MIN_REACTUATION_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpenHouse {
+    /// When open house mode ends, as a Unix timestamp
+    pub until: i64,
+    /// How often the door is re-actuated, or `None` to open on every tap instead
+    pub interval_secs: Option<u64>,
+}
+
+impl OpenHouse {
+    fn is_expired(&self) -> bool {
+        Utc::now().timestamp() >= self.until
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lockdown {
+    /// When lockdown started, as a Unix timestamp
+    pub since: i64,
+    /// Who started it, e.g. `websocket`, `admin_api` or `tag:<passport id>`
+    pub source: String,
+}
+
+static OPEN_HOUSE: LazyLock<watch::Sender<Option<OpenHouse>>> =
+    LazyLock::new(|| watch::Sender::new(None));
+
+static LOCKDOWN: LazyLock<watch::Sender<Option<Lockdown>>> =
+    LazyLock::new(|| watch::Sender::new(load_lockdown()));
+
+fn lockdown_state_path() -> PathBuf {
+    env::var("LOCKDOWN_STATE_PATH")
+        .unwrap_or_else(|_| DEFAULT_LOCKDOWN_STATE_PATH.into())
+        .into()
+}
+
+/// Restores lockdown from the previous run
+///
+/// A state file which exists but cannot be read keeps the door locked down, as
+/// it can only have been written by a lockdown that was never lifted.
+fn load_lockdown() -> Option<Lockdown> {
+    let path = lockdown_state_path();
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            error!(path = %path.display(), error = %e, "failed to read lockdown state, staying locked down");
+            return Some(Lockdown {
+                since: Utc::now().timestamp(),
+                source: String::from("unreadable_state"),
+            });
+        }
+    };
+    let lockdown = serde_json::from_str(&contents).unwrap_or_else(|e| {
+        error!(path = %path.display(), error = %e, "failed to parse lockdown state, staying locked down");
+        Lockdown {
+            since: Utc::now().timestamp(),
+            source: String::from("unreadable_state"),
+        }
+    });
+    warn!(?lockdown, "door is still locked down from a previous run");
+    Some(lockdown)
+}
+
+fn save_lockdown(lockdown: Option<&Lockdown>) -> io::Result<()> {
+    let path = lockdown_state_path();
+    let Some(lockdown) = lockdown else {
+        return match fs::remove_file(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        };
+    };
+    persist::write_json(&path, lockdown)
+}
+
+fn max_open_house() -> Duration {
+    env::var("OPEN_HOUSE_MAX_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .map_or(DEFAULT_OPEN_HOUSE_MAX, Duration::from_secs)
+}
+
+/// Starts open house mode, replacing any open house already running
+///
+/// The duration is capped at `OPEN_HOUSE_MAX_SECS` and the interval raised to
+/// [`MIN_REACTUATION_INTERVAL`]. `source` is recorded in the audit log.
+///
+/// Returns `None` without starting anything while the door is locked down.
+pub fn start_open_house(
+    duration: Duration,
+    interval: Option<Duration>,
+    source: &str,
+) -> Option<OpenHouse> {
+    // Held until open house is set, so a lockdown cannot start in between and miss it
+    let lockdown = LOCKDOWN.borrow();
+    if lockdown.is_some() {
+        warn!(source, "refusing to start open house during lockdown");
+        return None;
+    }
+
+    let max = max_open_house();
+    if duration > max {
+        warn!(
+            requested_secs = duration.as_secs(),
+            max_secs = max.as_secs(),
+            "open house duration capped"
+        );
+    }
+    let duration = duration.min(max);
+    let open_house = OpenHouse {
+        until: Utc::now()
+            .timestamp()
+            .saturating_add(i64::try_from(duration.as_secs()).unwrap_or(i64::MAX)),
+        interval_secs: interval.map(|i| i.max(MIN_REACTUATION_INTERVAL).as_secs()),
+    };
+
+    OPEN_HOUSE.send_replace(Some(open_house));
+    drop(lockdown);
+    info!(
+        until = open_house.until,
+        interval_secs = open_house.interval_secs,
+        source,
+        "open house started"
+    );
+    audit::record(&AuditEvent::OpenHouse {
+        until: Some(open_house.until),
+        source: source.to_string(),
+    });
+    Some(open_house)
+}
+
+/// Ends open house mode early, returning whether it was running
+pub fn stop_open_house(source: &str) -> bool {
+    let was_running = OPEN_HOUSE
+        .send_replace(None)
+        .is_some_and(|o| !o.is_expired());
+    if was_running {
+        info!(source, "open house stopped");
+        audit::record(&AuditEvent::OpenHouse {
+            until: None,
+            source: source.to_string(),
+        });
+    }
+    was_running
+}
+
+/// The running open house, if any
+///
+/// An expired open house is cleared here, which also notifies subscribers.
+#[must_use]
+pub fn open_house() -> Option<OpenHouse> {
+    OPEN_HOUSE.send_if_modified(|open_house| {
+        if open_house.is_some_and(|o| o.is_expired()) {
+            *open_house = None;
+            info!("open house expired");
+            audit::record(&AuditEvent::OpenHouse {
+                until: None,
+                source: String::from("expired"),
+            });
+            true
+        } else {
+            false
+        }
+    });
+    *OPEN_HOUSE.borrow()
+}
+
+/// Watches for open house mode starting or stopping
+#[must_use]
+pub fn subscribe_open_house() -> watch::Receiver<Option<OpenHouse>> {
+    OPEN_HOUSE.subscribe()
+}
+
+/// Locks the door down, ending any open house, and saves it for the next run
+///
+/// Returns whether the door was not already locked down.
+pub fn start_lockdown(source: &str) -> bool {
+    let lockdown = Lockdown {
+        since: Utc::now().timestamp(),
+        source: source.to_string(),
+    };
+    let started = LOCKDOWN.send_if_modified(|current| {
+        if current.is_some() {
+            return false;
+        }
+        // Saved while the state is held so a concurrent lift cannot be overwritten
+        if let Err(e) = save_lockdown(Some(&lockdown)) {
+            error!(error = %e, "failed to save lockdown state, it will not survive a restart");
+        }
+        *current = Some(lockdown);
+        true
+    });
+
+    if started {
+        warn!(source, "door locked down");
+        audit::record(&AuditEvent::Lockdown {
+            active: true,
+            source: source.to_string(),
+        });
+        stop_open_house("lockdown");
+    }
+    started
+}
+
+/// Lifts lockdown, returning whether the door was locked down
+pub fn lift_lockdown(source: &str) -> bool {
+    let lifted = LOCKDOWN.send_if_modified(|current| {
+        if current.is_none() {
+            return false;
+        }
+        if let Err(e) = save_lockdown(None) {
+            error!(error = %e, "failed to remove lockdown state, it will return after a restart");
+        }
+        *current = None;
+        true
+    });
+
+    if lifted {
+        info!(source, "lockdown lifted");
+        audit::record(&AuditEvent::Lockdown {
+            active: false,
+            source: source.to_string(),
+        });
+    }
+    lifted
+}
+
+/// The current lockdown, if any
+#[must_use]
+pub fn lockdown() -> Option<Lockdown> {
+    LOCKDOWN.borrow().clone()
+}
+
+#[must_use]
+pub fn is_locked_down() -> bool {
+    LOCKDOWN.borrow().is_some()
+}
@@ -0,0 +1,25 @@
+//! Helpers for state saved to disk between runs
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::Serialize;
+
+/// Writes `value` as JSON to `path`, replacing the file in one step
+///
+/// The JSON is written alongside and renamed over, so a crash or power cut
+/// never leaves half a file behind.
+///
+/// # Errors
+///
+/// Will error if the value cannot be serialized or the file cannot be written
+pub fn write_json(path: &Path, value: &impl Serialize) -> io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    fs::write(
+        &tmp_path,
+        serde_json::to_vec(value).map_err(io::Error::from)?,
+    )?;
+    fs::File::open(&tmp_path)?.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
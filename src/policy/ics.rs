@@ -0,0 +1,169 @@
+//! Minimal ICS reader for one-off events
+//!
+//! Only `DTSTART` and `DTEND` are read, so `DURATION` is ignored and recurring
+//! events (`RRULE`) only count for their first occurrence.
+
+use chrono::{Local, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta};
+
+use super::{Exception, Result};
+
+/// Joins folded lines, which continue with a leading space or tab
+fn unfold(source: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in source.lines() {
+        if let Some(continuation) = line.strip_prefix([' ', '\t'])
+            && let Some(last) = lines.last_mut()
+        {
+            last.push_str(continuation);
+        } else {
+            lines.push(line.to_string());
+        }
+    }
+    lines
+}
+
+/// Parses a `DTSTART`/`DTEND` value into local time
+///
+/// UTC times (`Z` suffix) are converted, while floating and `TZID` times are
+/// assumed to already be in the door's local time zone. Returns whether the
+/// value was a whole date.
+fn parse_value(params: &str, value: &str) -> Result<(NaiveDateTime, bool)> {
+    if params.contains("VALUE=DATE;") || params.ends_with("VALUE=DATE") || value.len() == 8 {
+        let date = NaiveDate::parse_from_str(value, "%Y%m%d")?;
+        return Ok((date.and_time(NaiveTime::MIN), true));
+    }
+
+    if let Some(utc) = value.strip_suffix('Z') {
+        let at = NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S")?;
+        return Ok((at.and_utc().with_timezone(&Local).naive_local(), false));
+    }
+
+    Ok((
+        NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")?,
+        false,
+    ))
+}
+
+/// Reads every `VEVENT` in an ICS file as an open exception
+pub(super) fn parse_events(source: &str) -> Result<Vec<Exception>> {
+    let mut events = Vec::new();
+    let mut start: Option<(NaiveDateTime, bool)> = None;
+    let mut end: Option<NaiveDateTime> = None;
+    let mut in_event = false;
+
+    for line in unfold(source) {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let (name, params) = name.split_once(';').unwrap_or((name, ""));
+
+        match name {
+            "BEGIN" if value == "VEVENT" => {
+                in_event = true;
+                start = None;
+                end = None;
+            }
+            "END" if value == "VEVENT" => {
+                in_event = false;
+                let Some((start, all_day)) = start else {
+                    continue;
+                };
+                // Events without an end last a day if all-day, otherwise are instants
+                let end = end.unwrap_or(if all_day {
+                    start + TimeDelta::days(1)
+                } else {
+                    start
+                });
+                events.push(Exception {
+                    start,
+                    end,
+                    open: true,
+                });
+            }
+            "DTSTART" if in_event => start = Some(parse_value(params, value)?),
+            "DTEND" if in_event => end = Some(parse_value(params, value)?.0),
+            _ => {}
+        }
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn calendar(events: &str) -> String {
+        format!("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n{events}END:VCALENDAR\r\n")
+    }
+
+    #[test]
+    fn tzid_times_are_taken_as_local() {
+        let events = parse_events(&calendar(
+            "BEGIN:VEVENT\r\n\
+             DTSTART;TZID=America/Indiana/Indianapolis:20261016T180000\r\n\
+             DTEND;TZID=America/Indiana/Indianapolis:20261016T210000\r\n\
+             END:VEVENT\r\n",
+        ))
+        .unwrap();
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].start, at("2026-10-16 18:00"));
+        assert_eq!(events[0].end, at("2026-10-16 21:00"));
+        assert!(events[0].open);
+    }
+
+    #[test]
+    fn utc_times_are_converted() {
+        let events = parse_events(&calendar(
+            "BEGIN:VEVENT\r\nDTSTART:20261016T180000Z\r\nDTEND:20261016T210000Z\r\nEND:VEVENT\r\n",
+        ))
+        .unwrap();
+
+        let local = |s: &str| at(s).and_utc().with_timezone(&Local).naive_local();
+        assert_eq!(events[0].start, local("2026-10-16 18:00"));
+        assert_eq!(events[0].end, local("2026-10-16 21:00"));
+    }
+
+    #[test]
+    fn all_day_events_last_the_day() {
+        let events = parse_events(&calendar(
+            "BEGIN:VEVENT\r\nDTSTART;VALUE=DATE:20261224\r\nEND:VEVENT\r\n\
+             BEGIN:VEVENT\r\nDTSTART;VALUE=DATE:20261226\r\nDTEND;VALUE=DATE:20261228\r\nEND:VEVENT\r\n",
+        ))
+        .unwrap();
+
+        assert_eq!(events[0].start, at("2026-12-24 00:00"));
+        assert_eq!(events[0].end, at("2026-12-25 00:00"));
+        assert_eq!(events[1].start, at("2026-12-26 00:00"));
+        assert_eq!(events[1].end, at("2026-12-28 00:00"));
+    }
+
+    #[test]
+    fn folded_lines_and_time_zone_definitions() {
+        let events = parse_events(&calendar(
+            "BEGIN:VTIMEZONE\r\nTZID:America/Chicago\r\nBEGIN:STANDARD\r\n\
+             DTSTART:19701101T020000\r\nEND:STANDARD\r\nEND:VTIMEZONE\r\n\
+             BEGIN:VEVENT\r\nDTSTART;TZID=America/\r\n Chicago:20261016T180000\r\nEND:VEVENT\r\n",
+        ))
+        .unwrap();
+
+        // The time zone's DTSTART is not an event, and an event without an end is an instant
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].start, at("2026-10-16 18:00"));
+        assert_eq!(events[0].end, at("2026-10-16 18:00"));
+    }
+
+    #[test]
+    fn invalid_times_are_errors() {
+        assert!(
+            parse_events(&calendar(
+                "BEGIN:VEVENT\r\nDTSTART:2026-10-16\r\nEND:VEVENT\r\n"
+            ))
+            .is_err()
+        );
+    }
+}
@@ -0,0 +1,432 @@
+//! Access policies evaluated after a passport has been validated
+//!
+//! Policies are loaded from the JSON file at `ACCESS_POLICY_PATH`. When it is
+//! unset every valid passport is let in at any hour.
+
+mod ics;
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::path::Path;
+use std::{env, fs};
+
+use chrono::{Datelike, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, TimeZone, Weekday};
+use serde::Deserialize;
+
+type Result<T> = std::result::Result<T, Box<dyn Error + Send + Sync>>;
+
+/// How far ahead to look for the next opening
+const LOOKAHEAD_DAYS: i64 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Organizer,
+    Member,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Allow,
+    /// Denied because the door is closed to this role right now
+    Closed {
+        /// Next time the door opens for this role, as a Unix timestamp
+        next_open: Option<i64>,
+    },
+}
+
+#[derive(Deserialize)]
+struct WeeklyWindowConfig {
+    day: String,
+    start: String,
+    end: String,
+}
+
+#[derive(Deserialize)]
+struct ExceptionConfig {
+    start: String,
+    end: String,
+    /// Whether the door is open (`true`) or closed (`false`) during the exception
+    open: bool,
+}
+
+#[derive(Deserialize)]
+struct PolicyConfig {
+    /// Passport IDs which are always let in
+    #[serde(default)]
+    organizers: Vec<i32>,
+    /// Passport IDs whose tap starts or lifts lockdown
+    #[serde(default)]
+    lockdown_tags: Vec<i32>,
+    #[serde(default)]
+    open_hours: Vec<WeeklyWindowConfig>,
+    #[serde(default)]
+    exceptions: Vec<ExceptionConfig>,
+    /// ICS file whose events are treated as open exceptions
+    calendar: Option<String>,
+}
+
+/// Just the lockdown tags, so they can be read from a policy which is otherwise broken
+#[derive(Deserialize)]
+struct LockdownTagsConfig {
+    #[serde(default)]
+    lockdown_tags: Vec<i32>,
+}
+
+/// A weekly opening, which may run past midnight into the next day
+#[derive(Debug, Clone, Copy)]
+struct WeeklyWindow {
+    day: Weekday,
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl WeeklyWindow {
+    /// The occurrence of this window starting on `date`, if `date` is the right weekday
+    fn on(&self, date: NaiveDate) -> Option<(NaiveDateTime, NaiveDateTime)> {
+        if date.weekday() != self.day {
+            return None;
+        }
+        let start = date.and_time(self.start);
+        let end_date = if self.end <= self.start {
+            date.succ_opt()?
+        } else {
+            date
+        };
+        Some((start, end_date.and_time(self.end)))
+    }
+}
+
+/// Times of day, such as quiet hours, written like `22:00-08:00,13:00-14:00`
+///
+/// Windows ending before they start run past midnight.
+#[derive(Debug, Clone)]
+pub struct DailyHours(Vec<(NaiveTime, NaiveTime)>);
+
+impl DailyHours {
+    /// Parses comma-separated `HH:MM-HH:MM` windows, returning `None` if any is invalid
+    #[must_use]
+    pub fn parse(value: &str) -> Option<Self> {
+        value
+            .split(',')
+            .map(|window| {
+                let (start, end) = window.trim().split_once('-')?;
+                Some((parse_time(start.trim()).ok()?, parse_time(end.trim()).ok()?))
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(Self)
+    }
+
+    /// Whether the local time is within any of the windows
+    #[must_use]
+    pub fn contains_now(&self) -> bool {
+        let now = Local::now().time();
+        self.0.iter().any(|(start, end)| {
+            if start <= end {
+                *start <= now && now < *end
+            } else {
+                *start <= now || now < *end
+            }
+        })
+    }
+}
+
+/// A one-off period overriding the weekly schedule
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Exception {
+    pub(crate) start: NaiveDateTime,
+    pub(crate) end: NaiveDateTime,
+    pub(crate) open: bool,
+}
+
+impl Exception {
+    fn contains(&self, at: NaiveDateTime) -> bool {
+        self.start <= at && at < self.end
+    }
+}
+
+pub struct AccessPolicy {
+    organizers: HashSet<i32>,
+    lockdown_tags: HashSet<i32>,
+    weekly: Vec<WeeklyWindow>,
+    exceptions: Vec<Exception>,
+}
+
+fn parse_time(s: &str) -> Result<NaiveTime> {
+    Ok(NaiveTime::parse_from_str(s, "%H:%M")?)
+}
+
+fn parse_datetime(s: &str) -> Result<NaiveDateTime> {
+    Ok(NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M")
+        .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S"))?)
+}
+
+impl AccessPolicy {
+    /// Loads the policy from `ACCESS_POLICY_PATH`, returning `None` if it is unset
+    ///
+    /// # Errors
+    ///
+    /// Will error if the policy file or its calendar cannot be read or parsed
+    pub fn from_env() -> Result<Option<Self>> {
+        match env::var("ACCESS_POLICY_PATH") {
+            Ok(path) => Self::load(Path::new(&path)).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Loads a policy file, resolving its calendar relative to the file
+    ///
+    /// # Errors
+    ///
+    /// Will error if the policy file or its calendar cannot be read or parsed
+    pub fn load(path: &Path) -> Result<Self> {
+        let config: PolicyConfig = serde_json::from_str(&fs::read_to_string(path)?)?;
+
+        let weekly = config
+            .open_hours
+            .iter()
+            .map(|w| {
+                Ok(WeeklyWindow {
+                    day: w
+                        .day
+                        .parse()
+                        .map_err(|_| format!("invalid day: {}", w.day))?,
+                    start: parse_time(&w.start)?,
+                    end: parse_time(&w.end)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut exceptions = config
+            .exceptions
+            .iter()
+            .map(|e| {
+                Ok(Exception {
+                    start: parse_datetime(&e.start)?,
+                    end: parse_datetime(&e.end)?,
+                    open: e.open,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if let Some(calendar) = config.calendar {
+            let calendar_path = path.parent().unwrap_or(Path::new(".")).join(calendar);
+            exceptions.extend(ics::parse_events(&fs::read_to_string(calendar_path)?)?);
+        }
+
+        Ok(Self {
+            organizers: config.organizers.into_iter().collect(),
+            lockdown_tags: config.lockdown_tags.into_iter().collect(),
+            weekly,
+            exceptions,
+        })
+    }
+
+    /// Reads only the lockdown tags from `ACCESS_POLICY_PATH`, so they keep
+    /// working when the rest of the policy cannot be loaded
+    ///
+    /// # Errors
+    ///
+    /// Will error if the policy file cannot be read or is not valid JSON
+    pub fn lockdown_tags_from_env() -> Result<HashSet<i32>> {
+        let Ok(path) = env::var("ACCESS_POLICY_PATH") else {
+            return Ok(HashSet::new());
+        };
+        let config: LockdownTagsConfig = serde_json::from_str(&fs::read_to_string(path)?)?;
+        Ok(config.lockdown_tags.into_iter().collect())
+    }
+
+    #[must_use]
+    pub fn role_of(&self, passport_id: i32) -> Role {
+        if self.organizers.contains(&passport_id) {
+            Role::Organizer
+        } else {
+            Role::Member
+        }
+    }
+
+    /// Whether tapping this passport starts or lifts lockdown
+    #[must_use]
+    pub fn is_lockdown_tag(&self, passport_id: i32) -> bool {
+        self.lockdown_tags.contains(&passport_id)
+    }
+
+    /// Whether members may enter at the given local time
+    ///
+    /// Closed exceptions take precedence over open ones, which take precedence
+    /// over the weekly schedule.
+    fn is_open(&self, at: NaiveDateTime) -> bool {
+        let mut exceptions = self.exceptions.iter().filter(|e| e.contains(at));
+        if let Some(first) = exceptions.next() {
+            return first.open && exceptions.all(|e| e.open);
+        }
+
+        let today = at.date();
+        [today.pred_opt(), Some(today)]
+            .into_iter()
+            .flatten()
+            .flat_map(|date| self.weekly.iter().filter_map(move |w| w.on(date)))
+            .any(|(start, end)| start <= at && at < end)
+    }
+
+    /// Earliest time after `after` at which the door opens for members
+    fn next_opening(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let horizon = after + TimeDelta::days(LOOKAHEAD_DAYS);
+        let weekly_starts = (0..=LOOKAHEAD_DAYS)
+            .filter_map(|days| after.date().checked_add_signed(TimeDelta::days(days)))
+            .flat_map(|date| self.weekly.iter().filter_map(move |w| w.on(date)))
+            .map(|(start, _)| start);
+        // An opening can also begin when an exception starts or a closure ends
+        let exception_bounds = self
+            .exceptions
+            .iter()
+            .map(|e| if e.open { e.start } else { e.end });
+
+        weekly_starts
+            .chain(exception_bounds)
+            .filter(|&candidate| candidate > after && candidate <= horizon)
+            .filter(|&candidate| self.is_open(candidate))
+            .min()
+    }
+
+    /// Whether members may enter right now
+    #[must_use]
+    pub fn is_open_now(&self) -> bool {
+        self.is_open(Local::now().naive_local())
+    }
+
+    /// Decides whether a passport holder with the given role may enter now
+    #[must_use]
+    pub fn evaluate(&self, role: Role) -> Decision {
+        if role == Role::Organizer {
+            return Decision::Allow;
+        }
+
+        let now = Local::now().naive_local();
+        if self.is_open(now) {
+            Decision::Allow
+        } else {
+            Decision::Closed {
+                next_open: self
+                    .next_opening(now)
+                    .and_then(|at| Local.from_local_datetime(&at).earliest())
+                    .map(|at| at.timestamp()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn window(day: Weekday, start: &str, end: &str) -> WeeklyWindow {
+        WeeklyWindow {
+            day,
+            start: parse_time(start).unwrap(),
+            end: parse_time(end).unwrap(),
+        }
+    }
+
+    fn exception(start: &str, end: &str, open: bool) -> Exception {
+        Exception {
+            start: at(start),
+            end: at(end),
+            open,
+        }
+    }
+
+    fn policy(weekly: Vec<WeeklyWindow>, exceptions: Vec<Exception>) -> AccessPolicy {
+        AccessPolicy {
+            organizers: HashSet::from([1]),
+            lockdown_tags: HashSet::new(),
+            weekly,
+            exceptions,
+        }
+    }
+
+    // 2026-10-16 is a Friday
+
+    #[test]
+    fn overnight_window_runs_past_midnight() {
+        let policy = policy(vec![window(Weekday::Fri, "22:00", "02:00")], vec![]);
+
+        assert!(!policy.is_open(at("2026-10-16 21:59")));
+        assert!(policy.is_open(at("2026-10-16 22:00")));
+        assert!(policy.is_open(at("2026-10-17 01:59")));
+        assert!(!policy.is_open(at("2026-10-17 02:00")));
+        // Only Fridays open, not the night after Saturday
+        assert!(!policy.is_open(at("2026-10-17 23:00")));
+    }
+
+    #[test]
+    fn exceptions_override_the_weekly_schedule() {
+        let weekly = vec![window(Weekday::Fri, "18:00", "23:00")];
+
+        let closed = policy(
+            weekly.clone(),
+            vec![exception("2026-10-16 20:00", "2026-10-16 21:00", false)],
+        );
+        assert!(closed.is_open(at("2026-10-16 19:00")));
+        assert!(!closed.is_open(at("2026-10-16 20:30")));
+
+        let open = policy(
+            weekly.clone(),
+            vec![exception("2026-10-17 12:00", "2026-10-17 14:00", true)],
+        );
+        assert!(open.is_open(at("2026-10-17 13:00")));
+        assert!(!open.is_open(at("2026-10-17 14:00")));
+
+        // Closures win over openings they overlap
+        let both = policy(
+            weekly,
+            vec![
+                exception("2026-10-16 12:00", "2026-10-16 22:00", true),
+                exception("2026-10-16 19:00", "2026-10-16 20:00", false),
+            ],
+        );
+        assert!(both.is_open(at("2026-10-16 13:00")));
+        assert!(!both.is_open(at("2026-10-16 19:30")));
+    }
+
+    #[test]
+    fn next_opening_follows_closures() {
+        let policy = policy(
+            vec![window(Weekday::Fri, "18:00", "23:00")],
+            vec![exception("2026-10-16 17:00", "2026-10-16 20:00", false)],
+        );
+
+        // The closure ends before the window does
+        assert_eq!(
+            policy.next_opening(at("2026-10-16 17:30")),
+            Some(at("2026-10-16 20:00"))
+        );
+        // Then the following Friday
+        assert_eq!(
+            policy.next_opening(at("2026-10-16 23:00")),
+            Some(at("2026-10-23 18:00"))
+        );
+    }
+
+    #[test]
+    fn organizers_are_always_let_in() {
+        let policy = policy(vec![], vec![]);
+        assert_eq!(policy.role_of(1), Role::Organizer);
+        assert_eq!(policy.evaluate(Role::Organizer), Decision::Allow);
+        assert!(matches!(
+            policy.evaluate(Role::Member),
+            Decision::Closed { next_open: None }
+        ));
+    }
+
+    #[test]
+    fn daily_hours_parse() {
+        assert!(DailyHours::parse("22:00-08:00,13:00-14:00").is_some());
+        assert!(DailyHours::parse("22:00").is_none());
+        assert!(DailyHours::parse("22:00-25:00").is_none());
+    }
+}
@@ -0,0 +1,172 @@
+//! Revoked passports, pushed by the server and checked before the ID server
+//!
+//! The server signs each update with a key listed in `REVOCATION_PUBLIC_KEYS`.
+//! Updates are either a full snapshot of the list or a delta from a given
+//! version. A delta that does not start from the version held here means an
+//! update was missed, so the full list has to be sent again.
+//!
+//! The list is saved to `REVOCATION_LIST_PATH` so it applies straight away
+//! after a restart, even before the websocket connects.
+
+use std::collections::BTreeSet;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{LazyLock, PoisonError, RwLock};
+
+use base64::Engine;
+use base64::engine::general_purpose;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use crate::keys;
+use crate::persist;
+
+type Result<T> = std::result::Result<T, Box<dyn Error + Send + Sync>>;
+
+/// Revocation list location used when `REVOCATION_LIST_PATH` is unset
+pub const DEFAULT_REVOCATION_LIST_PATH: &str = "revocations.json";
+
+/// Signed contents of a revocation update
+#[derive(Debug, Deserialize)]
+struct Update {
+    /// Version of the list once this update is applied
+    version: u64,
+    /// Version this delta applies on top of, or `None` for a full snapshot
+    base_version: Option<u64>,
+    /// Passports revoked, or every revoked passport for a snapshot
+    #[serde(default)]
+    revoked: Vec<i32>,
+    /// Passports no longer revoked, ignored for a snapshot
+    #[serde(default)]
+    unrevoked: Vec<i32>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RevocationList {
+    /// `None` until the first snapshot has been received
+    version: Option<u64>,
+    revoked: BTreeSet<i32>,
+}
+
+/// What happened to a revocation update
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Applied {
+    /// The list is now at this version
+    Updated(u64),
+    /// The update was not newer than the list, which is still at this version
+    AlreadyCurrent(u64),
+    /// The update is a delta from a version other than the one held here
+    OutOfSync { have: Option<u64> },
+}
+
+static LIST: LazyLock<RwLock<RevocationList>> = LazyLock::new(|| RwLock::new(load()));
+
+fn list_path() -> PathBuf {
+    env::var("REVOCATION_LIST_PATH")
+        .unwrap_or_else(|_| DEFAULT_REVOCATION_LIST_PATH.into())
+        .into()
+}
+
+fn load() -> RevocationList {
+    let path = list_path();
+    let list = fs::read_to_string(&path)
+        .map_err(Box::<dyn Error + Send + Sync>::from)
+        .and_then(|contents| Ok(serde_json::from_str::<RevocationList>(&contents)?));
+    match list {
+        Ok(list) => {
+            info!(
+                version = list.version,
+                revoked = list.revoked.len(),
+                "loaded revocation list"
+            );
+            list
+        }
+        Err(e)
+            if e.downcast_ref::<io::Error>().map(io::Error::kind)
+                == Some(io::ErrorKind::NotFound) =>
+        {
+            RevocationList::default()
+        }
+        Err(e) => {
+            // Starting over makes the server send the full list again
+            error!(path = %path.display(), error = %e, "failed to load revocation list, waiting for a full sync");
+            RevocationList::default()
+        }
+    }
+}
+
+/// Whether the passport has been revoked
+#[must_use]
+pub fn is_revoked(passport_id: i32) -> bool {
+    LIST.read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .revoked
+        .contains(&passport_id)
+}
+
+/// Version of the list held here, or `None` if no snapshot has been received
+#[must_use]
+pub fn version() -> Option<u64> {
+    LIST.read().unwrap_or_else(PoisonError::into_inner).version
+}
+
+/// Verifies and applies a signed update, saving the new list
+///
+/// `payload` is the JSON update exactly as signed, and `signature` its
+/// base64 encoded Ed25519 signature.
+///
+/// # Errors
+///
+/// Will error if the signature does not verify, the payload cannot be parsed,
+/// or the new list cannot be saved
+pub fn apply(payload: &str, signature: &str) -> Result<Applied> {
+    keys::verify(
+        &keys::from_env("REVOCATION_PUBLIC_KEYS")?,
+        payload.as_bytes(),
+        &general_purpose::STANDARD.decode(signature)?,
+    )?;
+    let update: Update = serde_json::from_str(payload)?;
+
+    let mut list = LIST.write().unwrap_or_else(PoisonError::into_inner);
+    if let Some(have) = list.version
+        && update.version <= have
+    {
+        return Ok(Applied::AlreadyCurrent(have));
+    }
+
+    let revoked = match update.base_version {
+        None => update.revoked.into_iter().collect(),
+        Some(base) if list.version == Some(base) => {
+            let mut revoked = list.revoked.clone();
+            revoked.extend(update.revoked);
+            for passport_id in &update.unrevoked {
+                revoked.remove(passport_id);
+            }
+            revoked
+        }
+        Some(base) => {
+            warn!(
+                have = list.version,
+                base, "revocation delta does not apply to the list held here"
+            );
+            return Ok(Applied::OutOfSync { have: list.version });
+        }
+    };
+
+    let updated = RevocationList {
+        version: Some(update.version),
+        revoked,
+    };
+    // Only replaced once saved, so the list on disk never falls behind
+    persist::write_json(&list_path(), &updated)?;
+    info!(
+        version = update.version,
+        revoked = updated.revoked.len(),
+        "revocation list updated"
+    );
+    *list = updated;
+    Ok(Applied::Updated(update.version))
+}
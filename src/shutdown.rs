@@ -0,0 +1,54 @@
+use std::sync::LazyLock;
+
+use tokio::sync::watch;
+use tokio::task;
+use tracing::{info, warn};
+
+static SHUTDOWN: LazyLock<watch::Sender<bool>> = LazyLock::new(|| watch::Sender::new(false));
+
+/// Asks every subsystem to wind down
+pub fn trigger() {
+    SHUTDOWN.send_if_modified(|triggered| !std::mem::replace(triggered, true));
+}
+
+/// Whether shutdown has been requested
+#[must_use]
+pub fn is_triggered() -> bool {
+    *SHUTDOWN.borrow()
+}
+
+/// Resolves once shutdown has been requested
+pub async fn wait() {
+    let mut rx = SHUTDOWN.subscribe();
+    // The sender lives in a static, so this can only fail if it is dropped at exit
+    let _ = rx.wait_for(|triggered| *triggered).await;
+}
+
+/// Spawns a task that triggers shutdown on SIGINT or SIGTERM
+pub fn spawn_signal_handler() {
+    task::spawn(async {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{SignalKind, signal};
+
+            let mut sigterm = match signal(SignalKind::terminate()) {
+                Ok(sigterm) => sigterm,
+                Err(e) => {
+                    warn!(error = %e, "failed to listen for SIGTERM");
+                    return;
+                }
+            };
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => info!("received SIGINT, shutting down"),
+                _ = sigterm.recv() => info!("received SIGTERM, shutting down"),
+            }
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = tokio::signal::ctrl_c().await;
+            info!("received Ctrl-C, shutting down");
+        }
+
+        trigger();
+    });
+}
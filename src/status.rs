@@ -0,0 +1,173 @@
+use std::sync::{LazyLock, Mutex, PoisonError};
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::camera::motion;
+use crate::events::{Envelope, Event, Link};
+use crate::hardware::door::diagnostics::{self, ModuleDiagnostics};
+use crate::hardware::door::link::{self, ModuleLink};
+use crate::health::{self, Status};
+use crate::mode::{self, Lockdown, OpenHouse};
+use crate::revocation;
+use crate::shutdown;
+
+static STARTED: LazyLock<Instant> = LazyLock::new(Instant::now);
+static ACTIVITY: LazyLock<Mutex<Activity>> = LazyLock::new(Mutex::default);
+
+/// The last scan read
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LastScan {
+    pub passport_id: Option<i32>,
+    /// Scan result, as recorded in the audit log
+    pub result: String,
+    /// Unix timestamp in milliseconds
+    pub timestamp: i64,
+}
+
+/// The last actuation of the door module
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LastActuation {
+    pub success: bool,
+    /// Unix timestamp in milliseconds
+    pub timestamp: i64,
+}
+
+/// Whether a link is up, as last published on the event bus
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LinkState {
+    pub link: Link,
+    pub connected: bool,
+    /// When the link last came up or went down, as a Unix timestamp in milliseconds
+    pub since: i64,
+}
+
+/// Recent activity at the door, followed from the event bus
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Activity {
+    pub last_scan: Option<LastScan>,
+    pub last_actuation: Option<LastActuation>,
+    pub links: Vec<LinkState>,
+}
+
+impl Activity {
+    fn apply(&mut self, envelope: Envelope) {
+        let timestamp = envelope.timestamp;
+        match envelope.event {
+            Event::Scan {
+                passport_id,
+                result,
+            } => {
+                self.last_scan = Some(LastScan {
+                    passport_id,
+                    result,
+                    timestamp,
+                });
+            }
+            Event::DoorActuated { success } => {
+                self.last_actuation = Some(LastActuation { success, timestamp });
+            }
+            Event::Connectivity { link, connected } => {
+                self.links.retain(|state| state.link != link);
+                self.links.push(LinkState {
+                    link,
+                    connected,
+                    since: timestamp,
+                });
+            }
+            Event::Auth(_) | Event::OpenRequested(_) => {}
+        }
+    }
+}
+
+/// Follows the event bus for the activity in status reports, until shutdown
+pub async fn track_activity(mut events: broadcast::Receiver<Envelope>) {
+    loop {
+        tokio::select! {
+            envelope = events.recv() => match envelope {
+                Ok(envelope) => ACTIVITY.lock().unwrap_or_else(PoisonError::into_inner).apply(envelope),
+                // Only the latest of each is kept, so missing some does not matter
+                Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return,
+            },
+            () = shutdown::wait() => return,
+        }
+    }
+}
+
+/// Health of a single subsystem, as reported to the server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubsystemReport {
+    pub name: String,
+    /// One of `starting`, `ready`, `degraded`, `disabled` or `stalled`
+    pub state: String,
+    pub detail: Option<String>,
+    pub restarts: u32,
+}
+
+/// Snapshot of the door opener's health
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatusReport {
+    pub version: String,
+    pub uptime_secs: u64,
+    pub subsystems: Vec<SubsystemReport>,
+    /// The running open house, if any
+    pub open_house: Option<OpenHouse>,
+    /// The current lockdown, if any
+    pub lockdown: Option<Lockdown>,
+    /// Version of the revocation list held, or `None` before the first full sync
+    pub revocation_version: Option<u64>,
+    /// Whether the camera sees someone at the door
+    pub someone_present: bool,
+    pub activity: Activity,
+    /// The wireless link to the door module, if it has one
+    pub door_link: Option<ModuleLink>,
+    /// Battery, firmware and diagnostics last read from the door module
+    pub door_diagnostics: Option<ModuleDiagnostics>,
+}
+
+/// Marks the start of the process for uptime reporting
+pub fn mark_started() {
+    LazyLock::force(&STARTED);
+}
+
+/// Builds a status report from the current subsystem health and door mode
+#[must_use]
+pub fn report() -> StatusReport {
+    let subsystems = health::snapshot()
+        .into_iter()
+        .map(|s| {
+            let alive = s.is_alive();
+            let (state, detail) = match s.status {
+                _ if !alive => ("stalled", None),
+                Status::Starting => ("starting", None),
+                Status::Ready => ("ready", None),
+                Status::Degraded(reason) => ("degraded", Some(reason)),
+                Status::Disabled => ("disabled", None),
+            };
+            SubsystemReport {
+                name: s.subsystem.to_string(),
+                state: state.to_string(),
+                detail,
+                restarts: s.restarts,
+            }
+        })
+        .collect();
+
+    StatusReport {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        uptime_secs: STARTED.elapsed().as_secs(),
+        subsystems,
+        open_house: mode::open_house(),
+        lockdown: mode::lockdown(),
+        revocation_version: revocation::version(),
+        someone_present: motion::is_present(),
+        activity: ACTIVITY
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone(),
+        door_link: link::state(),
+        door_diagnostics: diagnostics::state(),
+    }
+}
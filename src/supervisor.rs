@@ -0,0 +1,85 @@
+use std::any::Any;
+use std::time::{Duration, Instant};
+
+use tokio::task::{self, JoinHandle};
+use tokio::time;
+use tracing::{error, info, warn};
+
+use crate::health::{self, Subsystem};
+use crate::metrics;
+use crate::shutdown;
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// A task that ran at least this long before dying starts over at the initial backoff
+const BACKOFF_RESET_AFTER: Duration = Duration::from_secs(300);
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        (*message).to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("unknown panic payload")
+    }
+}
+
+/// Runs a subsystem task, restarting it with exponential backoff if it panics
+///
+/// `spawn` is called to start each run of the task, so it must be able to
+/// recreate everything the task needs. A task returning normally is treated as
+/// the subsystem having finished, and is not restarted. Nothing is restarted
+/// once shutdown has been triggered.
+///
+/// The returned handle resolves once the subsystem has stopped for good.
+#[must_use]
+pub fn supervise<F>(subsystem: Subsystem, mut spawn: F) -> JoinHandle<()>
+where
+    F: FnMut() -> JoinHandle<()> + Send + 'static,
+{
+    task::spawn(async move {
+        let mut backoff = INITIAL_BACKOFF;
+
+        loop {
+            let started = Instant::now();
+            let reason = match spawn().await {
+                Ok(()) => {
+                    info!(%subsystem, "subsystem task finished");
+                    return;
+                }
+                Err(e) if e.is_panic() => {
+                    format!("panicked: {}", panic_message(e.into_panic().as_ref()))
+                }
+                Err(e) => format!("task failed: {e}"),
+            };
+
+            if shutdown::is_triggered() {
+                warn!(%subsystem, reason, "subsystem died during shutdown");
+                return;
+            }
+
+            if started.elapsed() >= BACKOFF_RESET_AFTER {
+                backoff = INITIAL_BACKOFF;
+            }
+
+            error!(%subsystem, reason, retry_in_secs = backoff.as_secs(), "subsystem died; restarting");
+            sentry::with_scope(
+                |scope| scope.set_tag("subsystem", subsystem),
+                || {
+                    sentry::capture_message(
+                        &format!("{subsystem} subsystem {reason}"),
+                        sentry::Level::Error,
+                    );
+                },
+            );
+            metrics::record_subsystem_restart(&subsystem.to_string());
+            health::record_restart(subsystem, format!("restarting after task {reason}"));
+
+            tokio::select! {
+                () = time::sleep(backoff) => {}
+                () = shutdown::wait() => return,
+            }
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    })
+}
@@ -0,0 +1,270 @@
+use std::env;
+use std::io;
+use std::time::Duration;
+
+use tokio::{task, time};
+use tracing::{info, warn};
+
+use crate::health::{self, Status, Subsystem, SubsystemHealth};
+
+/// Status update interval used when systemd has not enabled the watchdog
+const DEFAULT_NOTIFY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Subsystems which must be up and alive before systemd is told we are ready
+const READY_SUBSYSTEMS: [Subsystem; 3] = [Subsystem::Gui, Subsystem::Reader, Subsystem::Door];
+
+/// Sends `sd_notify` messages to the socket systemd gave us
+pub struct Notifier {
+    socket: Option<String>,
+}
+
+impl Notifier {
+    /// Creates a notifier for `NOTIFY_SOCKET`, doing nothing if it is unset
+    #[must_use]
+    pub fn from_env() -> Self {
+        Self::new(env::var("NOTIFY_SOCKET").ok())
+    }
+
+    /// Creates a notifier for the given socket path, where a leading `@` denotes
+    /// an abstract socket
+    #[must_use]
+    pub fn new(socket: Option<String>) -> Self {
+        Self { socket }
+    }
+
+    /// Sends a newline-separated list of `KEY=VALUE` assignments
+    ///
+    /// # Errors
+    ///
+    /// Will error if the datagram cannot be sent to the notify socket
+    pub fn notify(&self, state: &str) -> io::Result<()> {
+        match &self.socket {
+            Some(socket) => send(socket, state),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn send(socket: &str, state: &str) -> io::Result<()> {
+    use std::os::unix::net::UnixDatagram;
+
+    let datagram = UnixDatagram::unbound()?;
+    if let Some(name) = socket.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::linux::net::SocketAddrExt;
+            use std::os::unix::net::SocketAddr;
+
+            let addr = SocketAddr::from_abstract_name(name.as_bytes())?;
+            datagram.send_to_addr(state.as_bytes(), &addr)?;
+            return Ok(());
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = name;
+            return Err(io::ErrorKind::Unsupported.into());
+        }
+    }
+    datagram.send_to(state.as_bytes(), socket)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn send(_socket: &str, _state: &str) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Describes degraded and stalled subsystems for `STATUS=`
+fn describe(subsystems: &[SubsystemHealth]) -> String {
+    let problems: Vec<String> = subsystems
+        .iter()
+        .filter_map(|s| {
+            if !s.is_alive() {
+                Some(format!("{} stalled", s.subsystem))
+            } else if let Status::Degraded(reason) = &s.status {
+                Some(format!("{} degraded ({reason})", s.subsystem))
+            } else if s.status == Status::Starting {
+                Some(format!("{} starting", s.subsystem))
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    if problems.is_empty() {
+        String::from("All subsystems operational")
+    } else {
+        problems.join("; ")
+    }
+}
+
+/// Builds the notify messages sent on each watchdog tick
+#[derive(Default)]
+struct Reporter {
+    ready: bool,
+    last_status: String,
+}
+
+impl Reporter {
+    /// Message for the current subsystem health, empty if there is nothing to send
+    fn message(&mut self, subsystems: &[SubsystemHealth], watchdog: bool) -> String {
+        let status = describe(subsystems);
+        let mut message = if status == self.last_status {
+            String::new()
+        } else {
+            info!(status, "subsystem status changed");
+            format!("STATUS={status}\n")
+        };
+        self.last_status = status;
+
+        if !self.ready
+            && subsystems
+                .iter()
+                .filter(|s| READY_SUBSYSTEMS.contains(&s.subsystem))
+                .all(|s| s.is_up() && s.is_alive())
+        {
+            self.ready = true;
+            message.push_str("READY=1\n");
+        }
+
+        // Withholding the ping lets systemd restart us if something wedged
+        if watchdog && subsystems.iter().all(SubsystemHealth::is_alive) {
+            message.push_str("WATCHDOG=1\n");
+        }
+
+        message
+    }
+}
+
+/// Watchdog interval requested by systemd, halved so we ping well within it
+fn watchdog_interval() -> Option<Duration> {
+    env::var("WATCHDOG_USEC")
+        .ok()?
+        .parse::<u64>()
+        .ok()
+        .map(|usec| Duration::from_micros(usec / 2))
+}
+
+/// Spawns the task reporting readiness, liveness and status to systemd
+pub fn spawn_watchdog() {
+    let notifier = Notifier::from_env();
+    if notifier.socket.is_none() {
+        return;
+    }
+
+    let watchdog = watchdog_interval();
+    let mut interval = time::interval(watchdog.unwrap_or(DEFAULT_NOTIFY_INTERVAL));
+
+    task::spawn(async move {
+        let mut reporter = Reporter::default();
+
+        loop {
+            interval.tick().await;
+            let message = reporter.message(&health::snapshot(), watchdog.is_some());
+
+            if !message.is_empty()
+                && let Err(e) = notifier.notify(&message)
+            {
+                warn!(error = %e, "failed to notify systemd");
+            }
+        }
+    });
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::net::UnixDatagram;
+    use std::time::Instant;
+
+    use super::*;
+
+    fn health(subsystem: Subsystem, status: Status, last_beat: Option<Instant>) -> SubsystemHealth {
+        SubsystemHealth {
+            subsystem,
+            status,
+            last_beat,
+            restarts: 0,
+        }
+    }
+
+    /// Binds a stand-in for systemd's notify socket
+    fn notify_socket(name: &str) -> (UnixDatagram, String) {
+        let path = env::temp_dir().join(format!("openerapp-{name}-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path).unwrap();
+        (socket, path.to_string_lossy().into_owned())
+    }
+
+    fn receive(socket: &UnixDatagram) -> String {
+        let mut buf = [0; 1024];
+        let len = socket.recv(&mut buf).unwrap();
+        String::from_utf8_lossy(&buf[..len]).into_owned()
+    }
+
+    #[test]
+    fn sends_to_notify_socket() {
+        let (socket, path) = notify_socket("notify");
+        Notifier::new(Some(path.clone()))
+            .notify("READY=1\n")
+            .unwrap();
+        assert_eq!(receive(&socket), "READY=1\n");
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn reports_ready_watchdog_and_status() {
+        let (socket, path) = notify_socket("reporter");
+        let notifier = Notifier::new(Some(path.clone()));
+        let mut reporter = Reporter::default();
+        let now = Some(Instant::now());
+
+        let starting = [
+            health(Subsystem::Gui, Status::Ready, now),
+            health(Subsystem::Reader, Status::Starting, now),
+            health(Subsystem::Door, Status::Ready, now),
+        ];
+        notifier.notify(&reporter.message(&starting, true)).unwrap();
+        assert_eq!(receive(&socket), "STATUS=reader starting\nWATCHDOG=1\n");
+
+        let ready = [
+            health(Subsystem::Gui, Status::Ready, now),
+            health(Subsystem::Reader, Status::Ready, now),
+            health(Subsystem::Door, Status::Ready, now),
+        ];
+        notifier.notify(&reporter.message(&ready, true)).unwrap();
+        assert_eq!(
+            receive(&socket),
+            "STATUS=All subsystems operational\nREADY=1\nWATCHDOG=1\n"
+        );
+
+        // Readiness is only sent once, and an unchanged status is not repeated
+        notifier.notify(&reporter.message(&ready, true)).unwrap();
+        assert_eq!(receive(&socket), "WATCHDOG=1\n");
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn withholds_ready_and_watchdog_while_stalled() {
+        let mut reporter = Reporter::default();
+        let now = Some(Instant::now());
+        let stalled = [
+            health(Subsystem::Gui, Status::Ready, now),
+            health(Subsystem::Reader, Status::Ready, None),
+            health(Subsystem::Door, Status::Ready, now),
+        ];
+        assert_eq!(reporter.message(&stalled, true), "STATUS=reader stalled\n");
+        // Without a watchdog there is nothing more to say
+        assert_eq!(reporter.message(&stalled, false), "");
+
+        let ready = [
+            health(Subsystem::Gui, Status::Ready, now),
+            health(Subsystem::Reader, Status::Ready, now),
+            health(Subsystem::Door, Status::Ready, now),
+        ];
+        assert_eq!(
+            reporter.message(&ready, false),
+            "STATUS=All subsystems operational\nREADY=1\n"
+        );
+    }
+}
@@ -11,6 +11,8 @@ use serde_json::Value;
 
 use self_replace::self_replace;
 
+use crate::metrics;
+
 type Result<T> = std::result::Result<T, Box<dyn Error>>;
 
 /// Checks and performs updates, returns true if an update was performed
@@ -39,15 +41,18 @@ pub async fn update_check() -> bool {
         match update_result {
             Ok(()) => {
                 println!("Update successful!");
+                metrics::record_update_check("updated");
                 true
             }
             Err(e) => {
                 eprintln!("Update failed: {e}");
+                metrics::record_update_check("failed");
                 false
             }
         }
     } else {
         println!("No update required; current version is {}", current_version);
+        metrics::record_update_check("up_to_date");
         false
     }
 }
@@ -6,36 +6,444 @@ use async_tungstenite::{
     tokio::{ConnectStream, connect_async},
     tungstenite::{Bytes, Message},
 };
+use futures::future;
 use futures::prelude::*;
 
-use tokio::time::sleep;
+use reqwest::header::CONTENT_TYPE;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task;
+use tokio::time::{interval, sleep, timeout};
 use tracing::{error, info, warn};
+use uuid::Uuid;
 
-use crate::camera::capture_photo;
+use crate::alerts::{self, Alert};
+use crate::camera::preview::{self, Preview};
+use crate::camera::tap_photos::{self, TapPhoto};
+use crate::camera::{self, Photo};
+use crate::events::{self, Event, Link};
+use crate::guest::{self, GuestPass};
+use crate::gui::screensaver;
+use crate::hardware::door::{OpenRequest, OpenSource};
+use crate::health::{self, Status, Subsystem};
+use crate::metrics;
+use crate::mode;
+use crate::revocation::{self, Applied};
+use crate::shutdown;
+use crate::status::{self, StatusReport};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+/// Uploads taking longer than this fall back to sending the photo over the websocket
+const PHOTO_UPLOAD_TIMEOUT: Duration = Duration::from_secs(10);
+const PING_INTERVAL: Duration = Duration::from_secs(25);
+const STATUS_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+/// Photos larger than this are not sent inline when `PHOTO_INLINE_MAX_BYTES` is unset
+const DEFAULT_PHOTO_INLINE_MAX: usize = 64 * 1024;
 
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 #[serde(tag = "type")]
 enum WebSocketMessage {
-    Open,
+    /// Opens the door, optionally saying who asked and why, such as the phone bell
+    Open {
+        actor: Option<String>,
+        reason: Option<String>,
+    },
     OpenAck,
+    /// The door will not open, such as during lockdown, instead of an ack
+    OpenRefused {
+        reason: String,
+    },
     CapturePhoto,
-    PhotoResult { data: String },
+    /// Photo small enough to send inline, as a base64 data URL
+    PhotoResult {
+        data: String,
+    },
+    /// Larger photo, sent in the binary frame which follows this message
+    PhotoBinary {
+        id: String,
+        content_type: String,
+        size: usize,
+    },
+    /// Larger photo, uploaded to `PHOTO_UPLOAD_URL` with `id` in `X-Photo-Id`
+    PhotoUploaded {
+        id: String,
+        content_type: String,
+        size: usize,
+    },
+    GetStatus,
+    StatusReport(StatusReport),
+    /// Starts open house mode, answered with a status report
+    SetOpenHouse {
+        duration_secs: u64,
+        /// Re-actuation interval, or unset to open on every tap instead
+        interval_secs: Option<u64>,
+    },
+    /// Ends open house mode early, answered with a status report
+    StopOpenHouse,
+    /// Locks the door down until lifted, answered with a status report
+    StartLockdown,
+    /// Lifts lockdown, answered with a status report
+    LiftLockdown,
+    /// Sent before disconnecting when the door opener shuts down
+    Goodbye,
+    /// Something organizers should look at, such as a rate limit tripping
+    Alert(Alert),
+    /// Signed revocation list update, answered with an ack, a resync request or a rejection
+    RevocationUpdate {
+        /// JSON update exactly as signed
+        payload: String,
+        /// Base64 encoded Ed25519 signature of `payload`
+        signature: String,
+    },
+    /// The revocation list is now at `version`
+    RevocationAck {
+        version: u64,
+    },
+    /// A revocation delta was missed, so the full list has to be sent again
+    RevocationResync {
+        have_version: Option<u64>,
+    },
+    /// A revocation update could not be applied
+    RevocationRejected {
+        error: String,
+    },
+    /// Creates a guest pass, answered with the pass and its code
+    CreateGuestPass {
+        label: String,
+        duration_secs: u64,
+        /// How many times the pass opens the door, once if unset
+        max_uses: Option<u32>,
+    },
+    GuestPassCreated(GuestPass),
+    /// Lists active guest passes, answered with the list
+    ListGuestPasses,
+    /// Revokes a guest pass, answered with the remaining passes
+    RevokeGuestPass {
+        id: String,
+    },
+    GuestPasses {
+        passes: Vec<GuestPass>,
+    },
+    /// Opens the door for a guest, answered with a redemption or a rejection
+    RedeemGuestPass {
+        code: String,
+        /// Code shown at the door, proving the guest is there
+        presence_code: String,
+    },
+    GuestPassRedeemed {
+        id: String,
+        uses_left: u32,
+    },
+    GuestPassRejected {
+        error: String,
+    },
+    /// Photo taken during a tap, sent when `TAP_PHOTO_UPLOAD` is set
+    TapPhoto(TapPhoto),
+    /// Starts a low frame rate camera preview, which stops on its own
+    StartStream {
+        /// Frames per second, 2 if unset and at most 5
+        fps: Option<u32>,
+        /// How long to stream for, 60 seconds if unset and at most 10 minutes
+        duration_secs: Option<u64>,
+    },
+    /// Stops the camera preview early
+    StopStream,
+    /// Preview frame, sent as a JPEG in the binary frame which follows this message
+    StreamFrame {
+        seq: u64,
+        content_type: String,
+        size: usize,
+    },
+    /// The camera preview stopped, or could not start
+    StreamStopped {
+        reason: String,
+    },
+}
+
+/// Tells the server we are going offline and closes the connection
+async fn say_goodbye(write: &mut WebSocketSender<ConnectStream>) {
+    let goodbye = Message::Text(
+        serde_json::to_string(&WebSocketMessage::Goodbye)
+            .unwrap()
+            .into(),
+    );
+    if let Err(e) = write.send(goodbye).await {
+        warn!(error = ?e, "failed to send goodbye");
+    }
+    let _ = write.send(Message::Close(None)).await;
+    info!("websocket closed for shutdown");
+}
+
+async fn send_status_report(write: &mut WebSocketSender<ConnectStream>) {
+    let res = write
+        .send(Message::Text(
+            serde_json::to_string(&WebSocketMessage::StatusReport(status::report()))
+                .unwrap()
+                .into(),
+        ))
+        .await;
+    if let Err(e) = res {
+        error!(error = ?e, "failed to send status report");
+    }
+}
+
+async fn send_alert(write: &mut WebSocketSender<ConnectStream>, alert: Result<Alert, RecvError>) {
+    let alert = match alert {
+        Ok(alert) => alert,
+        Err(RecvError::Lagged(missed)) => {
+            warn!(missed, "dropped alerts while the websocket was busy");
+            return;
+        }
+        // The sender lives in a static and is never dropped
+        Err(RecvError::Closed) => return,
+    };
+    let res = write
+        .send(Message::Text(
+            serde_json::to_string(&WebSocketMessage::Alert(alert))
+                .unwrap()
+                .into(),
+        ))
+        .await;
+    if let Err(e) = res {
+        error!(error = ?e, "failed to send alert");
+    }
+}
+
+async fn send_tap_photo(
+    write: &mut WebSocketSender<ConnectStream>,
+    photo: Result<TapPhoto, RecvError>,
+) {
+    match photo {
+        Ok(photo) => send_message(write, &WebSocketMessage::TapPhoto(photo)).await,
+        Err(RecvError::Lagged(missed)) => {
+            warn!(missed, "dropped tap photos while the websocket was busy");
+        }
+        // The sender lives in a static and is never dropped
+        Err(RecvError::Closed) => {}
+    }
+}
+
+async fn send_revocation_reply(
+    write: &mut WebSocketSender<ConnectStream>,
+    payload: &str,
+    signature: &str,
+) {
+    let reply = match revocation::apply(payload, signature) {
+        Ok(Applied::Updated(version) | Applied::AlreadyCurrent(version)) => {
+            WebSocketMessage::RevocationAck { version }
+        }
+        Ok(Applied::OutOfSync { have }) => {
+            WebSocketMessage::RevocationResync { have_version: have }
+        }
+        Err(e) => {
+            error!(error = %e, "rejected revocation update");
+            WebSocketMessage::RevocationRejected {
+                error: e.to_string(),
+            }
+        }
+    };
+    let res = write
+        .send(Message::Text(serde_json::to_string(&reply).unwrap().into()))
+        .await;
+    if let Err(e) = res {
+        error!(error = ?e, "failed to send revocation reply");
+    }
+}
+
+async fn send_message(write: &mut WebSocketSender<ConnectStream>, msg: &WebSocketMessage) {
+    let res = write
+        .send(Message::Text(serde_json::to_string(msg).unwrap().into()))
+        .await;
+    if let Err(e) = res {
+        error!(error = ?e, "failed to send websocket message");
+    }
+}
+
+async fn upload_photo(url: &str, id: &str, photo: &Photo) -> Result<(), reqwest::Error> {
+    // Messages are not handled while the upload runs, so it must not hang
+    reqwest::Client::builder()
+        .timeout(PHOTO_UPLOAD_TIMEOUT)
+        .build()?
+        .post(url)
+        .bearer_auth(env::var("DOOR_OPENER_API_KEY").unwrap_or_default())
+        .header(CONTENT_TYPE, photo.format.content_type())
+        .header("X-Photo-Id", id)
+        .body(photo.bytes.clone())
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
 }
 
+/// Captures a photo and sends it inline, by HTTP upload, or as a binary frame
+async fn send_photo(write: &mut WebSocketSender<ConnectStream>) {
+    // Encoding takes a while, so it is kept off the async runtime
+    let photo = match task::spawn_blocking(camera::capture).await {
+        Ok(Ok(photo)) => photo,
+        Ok(Err(e)) => {
+            error!(error = %e, "failed to capture photo");
+            return;
+        }
+        Err(e) => {
+            error!(error = %e, "photo capture task failed");
+            return;
+        }
+    };
+
+    let inline_max = env::var("PHOTO_INLINE_MAX_BYTES")
+        .ok()
+        .and_then(|bytes| bytes.parse().ok())
+        .unwrap_or(DEFAULT_PHOTO_INLINE_MAX);
+    if photo.bytes.len() <= inline_max {
+        let data = photo.data_url();
+        send_message(write, &WebSocketMessage::PhotoResult { data }).await;
+        return;
+    }
+
+    let id = Uuid::new_v4().simple().to_string();
+    let content_type = photo.format.content_type().to_string();
+    let size = photo.bytes.len();
+    if let Ok(url) = env::var("PHOTO_UPLOAD_URL") {
+        match upload_photo(&url, &id, &photo).await {
+            Ok(()) => {
+                let uploaded = WebSocketMessage::PhotoUploaded {
+                    id,
+                    content_type,
+                    size,
+                };
+                send_message(write, &uploaded).await;
+                return;
+            }
+            Err(e) => warn!(error = %e, "failed to upload photo, sending it over the websocket"),
+        }
+    }
+
+    let header = WebSocketMessage::PhotoBinary {
+        id,
+        content_type,
+        size,
+    };
+    send_message(write, &header).await;
+    if let Err(e) = write.send(Message::Binary(photo.bytes.into())).await {
+        error!(error = ?e, "failed to send photo");
+    }
+}
+
+async fn handle_guest_pass_redemption<F>(
+    write: &mut WebSocketSender<ConnectStream>,
+    code: &str,
+    presence_code: &str,
+    open: &mut F,
+) where
+    F: FnMut(OpenRequest) + Send + 'static,
+{
+    let reply = match guest::redeem(code, presence_code, "websocket") {
+        Ok(pass) => {
+            open(OpenRequest::new(
+                OpenSource::GuestPass,
+                Some(pass.label),
+                "websocket",
+            ));
+            WebSocketMessage::GuestPassRedeemed {
+                id: pass.id,
+                uses_left: pass.uses_left,
+            }
+        }
+        Err(e) => {
+            warn!(error = %e, "rejected guest pass");
+            WebSocketMessage::GuestPassRejected {
+                error: e.to_string(),
+            }
+        }
+    };
+    send_message(write, &reply).await;
+}
+
+/// Waits for the next preview frame, or forever when no preview is running
+async fn next_preview_frame(preview: &mut Option<Preview>) -> Option<u64> {
+    match preview {
+        Some(preview) => preview.tick().await,
+        None => future::pending().await,
+    }
+}
+
+/// Sends the latest camera frame as part of a preview stream
+async fn send_preview_frame(write: &mut WebSocketSender<ConnectStream>, seq: u64) {
+    let bytes = match task::spawn_blocking(preview::frame).await {
+        Ok(Ok(bytes)) => bytes,
+        Ok(Err(e)) => {
+            // The camera may be reopening, so the stream carries on with the next frame
+            warn!(error = %e, seq, "failed to capture preview frame");
+            return;
+        }
+        Err(e) => {
+            error!(error = %e, "preview frame task failed");
+            return;
+        }
+    };
+
+    let header = WebSocketMessage::StreamFrame {
+        seq,
+        content_type: String::from("image/jpeg"),
+        size: bytes.len(),
+    };
+    send_message(write, &header).await;
+    if let Err(e) = write.send(Message::Binary(bytes.into())).await {
+        error!(error = ?e, "failed to send preview frame");
+    }
+}
+
+async fn start_preview(
+    write: &mut WebSocketSender<ConnectStream>,
+    preview: &mut Option<Preview>,
+    fps: Option<u32>,
+    duration_secs: Option<u64>,
+) {
+    // Stop any running stream first, so its end is audited before the new one starts
+    *preview = None;
+    match Preview::start(fps, duration_secs.map(Duration::from_secs), "websocket") {
+        Ok(started) => *preview = Some(started),
+        Err(e) => {
+            warn!(error = %e, "refused camera preview");
+            let stopped = WebSocketMessage::StreamStopped {
+                reason: e.to_string(),
+            };
+            send_message(write, &stopped).await;
+        }
+    }
+}
+
+#[allow(clippy::too_many_lines)]
 async fn handle_message<F>(
     write: &mut WebSocketSender<ConnectStream>,
     msg: Option<Result<Message, Error>>,
     open: &mut F,
+    preview: &mut Option<Preview>,
 ) -> Result<(), ()>
 where
-    F: FnMut() + Send + 'static,
+    F: FnMut(OpenRequest) + Send + 'static,
 {
     match msg {
         Some(Ok(Message::Text(t))) => {
+            screensaver::wake();
             if let Ok(msg) = serde_json::from_str(t.as_ref()) {
                 match msg {
-                    WebSocketMessage::Open => {
-                        open();
+                    WebSocketMessage::Open { actor, reason } => {
+                        let request = OpenRequest::new(
+                            OpenSource::Websocket,
+                            actor,
+                            reason.unwrap_or_else(|| String::from("remote")),
+                        );
+                        // Checked before acking, but refused requests are still sent
+                        // on so the refusal is recorded
+                        let refusal = request.refusal();
+                        open(request);
+                        if let Some(refusal) = refusal {
+                            let refused = WebSocketMessage::OpenRefused {
+                                reason: refusal.to_string(),
+                            };
+                            send_message(write, &refused).await;
+                            return Ok(());
+                        }
                         let res = write
                             .send(Message::Text(
                                 serde_json::to_string(&WebSocketMessage::OpenAck)
@@ -47,26 +455,91 @@ where
                             error!(error = ?e, "failed to send open ack");
                         }
                     }
-                    WebSocketMessage::CapturePhoto => {
-                        let photostring = capture_photo();
-                        if let Ok(photostring) = photostring {
-                            let res = write
-                                .send(Message::Text(
-                                    serde_json::to_string(&WebSocketMessage::PhotoResult {
-                                        data: photostring,
-                                    })
-                                    .unwrap()
-                                    .into(),
-                                ))
-                                .await;
-                            if let Err(e) = res {
-                                error!(error = ?e, "failed to send photo result");
-                            }
-                        } else {
-                            error!(error = ?photostring, "failed to capture photo");
+                    WebSocketMessage::CapturePhoto => send_photo(write).await,
+                    WebSocketMessage::GetStatus => send_status_report(write).await,
+                    WebSocketMessage::SetOpenHouse {
+                        duration_secs,
+                        interval_secs,
+                    } => {
+                        mode::start_open_house(
+                            Duration::from_secs(duration_secs),
+                            interval_secs.map(Duration::from_secs),
+                            "websocket",
+                        );
+                        send_status_report(write).await;
+                    }
+                    WebSocketMessage::StopOpenHouse => {
+                        mode::stop_open_house("websocket");
+                        send_status_report(write).await;
+                    }
+                    WebSocketMessage::RevocationUpdate { payload, signature } => {
+                        send_revocation_reply(write, &payload, &signature).await;
+                    }
+                    WebSocketMessage::StartLockdown => {
+                        mode::start_lockdown("websocket");
+                        send_status_report(write).await;
+                    }
+                    WebSocketMessage::LiftLockdown => {
+                        mode::lift_lockdown("websocket");
+                        send_status_report(write).await;
+                    }
+                    WebSocketMessage::CreateGuestPass {
+                        label,
+                        duration_secs,
+                        max_uses,
+                    } => {
+                        let pass = guest::create(
+                            label,
+                            Duration::from_secs(duration_secs),
+                            max_uses.unwrap_or(1),
+                            "websocket",
+                        );
+                        send_message(write, &WebSocketMessage::GuestPassCreated(pass)).await;
+                    }
+                    WebSocketMessage::ListGuestPasses => {
+                        let passes = guest::list();
+                        send_message(write, &WebSocketMessage::GuestPasses { passes }).await;
+                    }
+                    WebSocketMessage::RevokeGuestPass { id } => {
+                        guest::revoke(&id, "websocket");
+                        let passes = guest::list();
+                        send_message(write, &WebSocketMessage::GuestPasses { passes }).await;
+                    }
+                    WebSocketMessage::RedeemGuestPass {
+                        code,
+                        presence_code,
+                    } => {
+                        handle_guest_pass_redemption(write, &code, &presence_code, open).await;
+                    }
+                    WebSocketMessage::StartStream { fps, duration_secs } => {
+                        start_preview(write, preview, fps, duration_secs).await;
+                    }
+                    WebSocketMessage::StopStream => {
+                        if preview.take().is_some() {
+                            let stopped = WebSocketMessage::StreamStopped {
+                                reason: String::from("stopped"),
+                            };
+                            send_message(write, &stopped).await;
                         }
                     }
-                    WebSocketMessage::OpenAck | WebSocketMessage::PhotoResult { .. } => {
+                    WebSocketMessage::OpenAck
+                    | WebSocketMessage::OpenRefused { .. }
+                    | WebSocketMessage::PhotoResult { .. }
+                    | WebSocketMessage::PhotoBinary { .. }
+                    | WebSocketMessage::PhotoUploaded { .. }
+                    | WebSocketMessage::StatusReport(_)
+                    | WebSocketMessage::Goodbye
+                    | WebSocketMessage::Alert(_)
+                    | WebSocketMessage::RevocationAck { .. }
+                    | WebSocketMessage::RevocationResync { .. }
+                    | WebSocketMessage::RevocationRejected { .. }
+                    | WebSocketMessage::GuestPassCreated(_)
+                    | WebSocketMessage::GuestPasses { .. }
+                    | WebSocketMessage::GuestPassRedeemed { .. }
+                    | WebSocketMessage::GuestPassRejected { .. }
+                    | WebSocketMessage::TapPhoto(_)
+                    | WebSocketMessage::StreamFrame { .. }
+                    | WebSocketMessage::StreamStopped { .. } => {
                         // We send those and we should never receive them from the server
                         unreachable!("Server sent invalid sender-only packets!");
                     }
@@ -83,6 +556,10 @@ where
     Ok(())
 }
 
+fn disconnected() -> Status {
+    Status::Degraded(String::from("disconnected from API"))
+}
+
 /// Websocket entry
 ///
 /// # Panics
@@ -90,29 +567,48 @@ where
 /// Will panic if there is no API key found
 pub async fn ws_entry<F>(mut open: F)
 where
-    F: FnMut() + Send + 'static,
+    F: FnMut(OpenRequest) + Send + 'static,
 {
     let websocket_url = "wss://api.purduehackers.com/phonebell/door-opener";
 
-    loop {
-        let (socket, _resp) = match connect_async(websocket_url).await {
+    while !shutdown::is_triggered() {
+        health::beat(Subsystem::Websocket);
+
+        let connect_res = timeout(CONNECT_TIMEOUT, connect_async(websocket_url))
+            .await
+            .unwrap_or(Err(Error::Io(std::io::ErrorKind::TimedOut.into())));
+        let (socket, _resp) = match connect_res {
             Ok(x) => {
                 info!(url = websocket_url, "connected to websocket");
+                metrics::record_websocket_connected();
+                health::set_status(Subsystem::Websocket, Status::Ready);
+                events::publish(Event::Connectivity {
+                    link: Link::Websocket,
+                    connected: true,
+                });
                 x
             }
             Err(e) => {
+                health::set_status(Subsystem::Websocket, disconnected());
+                metrics::record_websocket_connect_failure();
                 warn!(
                     url = websocket_url,
                     retry_in_secs = 5,
                     error = %e,
                     "failed to connect to API websocket; retrying"
                 );
-                sleep(Duration::from_secs(5)).await;
+                tokio::select! {
+                    () = sleep(Duration::from_secs(5)) => {}
+                    () = shutdown::wait() => return,
+                }
                 continue;
             }
         };
 
         let (mut write, mut read) = socket.split();
+        // Subscribed before authenticating so no alert or photo raised while connected is missed
+        let mut alerts = alerts::subscribe();
+        let mut tap_photos = tap_photos::subscribe();
 
         write
             .send(Message::Text(
@@ -123,13 +619,50 @@ where
             .await
             .expect("write auth");
 
+        send_status_report(&mut write).await;
+        let mut status_interval = interval(STATUS_REPORT_INTERVAL);
+        // The first tick completes immediately, and we just sent a report
+        status_interval.tick().await;
+        // Kept across iterations, so other traffic does not hold back the pings
+        let mut ping_interval = interval(PING_INTERVAL);
+        ping_interval.tick().await;
+        // Dropped with the connection, so a stream never outlives its viewer
+        let mut preview: Option<Preview> = None;
+
         loop {
+            health::beat(Subsystem::Websocket);
+
             tokio::select! {
-                () = sleep(Duration::from_secs(25)) => {
+                _ = ping_interval.tick() => {
                     write.send(Message::Ping(Bytes::default())).await.expect("ping");
                 }
+                _ = status_interval.tick() => {
+                    send_status_report(&mut write).await;
+                }
+                alert = alerts.recv() => {
+                    send_alert(&mut write, alert).await;
+                }
+                photo = tap_photos.recv() => {
+                    send_tap_photo(&mut write, photo).await;
+                }
+                seq = next_preview_frame(&mut preview) => {
+                    if let Some(seq) = seq {
+                        send_preview_frame(&mut write, seq).await;
+                    } else {
+                        preview = None;
+                        let stopped = WebSocketMessage::StreamStopped {
+                            reason: String::from("timeout"),
+                        };
+                        send_message(&mut write, &stopped).await;
+                    }
+                }
+                () = shutdown::wait() => {
+                    say_goodbye(&mut write).await;
+                    metrics::record_websocket_disconnected();
+                    return;
+                }
                 msg = read.next() => {
-                    let res = handle_message(&mut write, msg, &mut open).await;
+                    let res = handle_message(&mut write, msg, &mut open, &mut preview).await;
                     if res.is_err() {
                         break;
                     }
@@ -137,6 +670,12 @@ where
             }
         }
 
+        metrics::record_websocket_disconnected();
+        health::set_status(Subsystem::Websocket, disconnected());
+        events::publish(Event::Connectivity {
+            link: Link::Websocket,
+            connected: false,
+        });
         warn!("websocket connection closed");
     }
 }